@@ -1,14 +1,9 @@
-use anyhow::{anyhow as error, Result};
-
-use futures::prelude::*;
-use redis::AsyncCommands;
+use anyhow::Result;
 
 use std::sync::{Arc, Mutex};
-struct ApplicationContext {
-    redis_connection: redis::aio::MultiplexedConnection,
-}
 
 use axum_test::application_factory::ApplicationFactory;
+use axum_test::config::AppConfig;
 use axum_test::websocket::redis_pubsub::RedisPubsubAdapter;
 
 #[tokio::main]
@@ -19,7 +14,7 @@ async fn main() -> Result<()> {
 
     dotenv::dotenv()?;
 
-    env_logger::init();
+    tracing_subscriber::fmt::init();
 
     //let client = redis::Client::open("redis://:87654321@127.0.0.1:6379")?;
     //let mut manager = client.get_tokio_connection_manager().await?;
@@ -53,8 +48,10 @@ async fn main() -> Result<()> {
     //handle.join();
     //
 
-    let fac = Arc::new(Mutex::new(ApplicationFactory::new().await?));
-    let mut adap = RedisPubsubAdapter::new("room::*", fac);
+    let fac = Arc::new(Mutex::new(
+        ApplicationFactory::new(Arc::new(AppConfig::load(&[])?)).await?,
+    ));
+    let _adap = RedisPubsubAdapter::new("room::*", fac);
 
     //let mut resv = adap.run()?;
 
@@ -65,19 +62,3 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
-
-fn pubsub_loop(mut con: redis::Connection) -> Result<()> {
-    let mut pubsub = con.as_pubsub();
-    pubsub.psubscribe("room::*")?;
-
-    loop {
-        let msg = pubsub.get_message()?;
-
-        let payload: std::result::Result<String, redis::RedisError> = msg.get_payload();
-
-        if let Ok(payload) = payload {
-            log::info!("Got  payload: {payload}");
-        }
-    }
-    Ok(())
-}
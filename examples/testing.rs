@@ -4,7 +4,7 @@ async fn main() {
         std::env::set_var("RUST_LOG", "info");
     }
 
-    env_logger::init();
+    tracing_subscriber::fmt::init();
     log::info!("tesing example!!");
     println!("Hello world example");
 }
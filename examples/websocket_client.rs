@@ -10,7 +10,7 @@ use tokio_tungstenite::{
     tungstenite::protocol::{frame::coding::CloseCode, CloseFrame, Message},
 };
 
-use anyhow::{anyhow as error, Result};
+use anyhow::Result;
 
 const N_CLIENTS: usize = 2; //set to desired number
 const SERVER: &str = "ws://127.0.0.1:3000/ws";
@@ -47,11 +47,11 @@ async fn main() -> Result<()> {
         std::env::set_var("RUST_LOG", "info");
     }
 
-    env_logger::init();
+    tracing_subscriber::fmt::init();
 
     let host = std::env::var("HOST_ADDRESS").unwrap_or(String::from("127.0.0.1:3000"));
 
-    let websocket_address = format!("ws://{host}/ws");
+    let _websocket_address = format!("ws://{host}/ws");
 
     let token = fetch_token(&host).await?;
 
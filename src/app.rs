@@ -2,10 +2,19 @@ pub mod dao;
 pub mod user;
 
 pub mod dto;
+pub mod index;
+pub mod migration;
+pub mod migrations;
+pub mod page;
+pub mod query;
+pub mod view;
 
 pub mod application_dao;
 pub mod application_service;
+pub mod cache;
 pub mod collections;
+pub mod crud_router;
 pub mod service;
+pub mod transaction;
 
 pub use dao::*;
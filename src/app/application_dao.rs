@@ -2,25 +2,150 @@ use crate::application_factory::ApplicationFactory;
 use std::sync::Arc;
 use std::sync::OnceLock;
 
-use anyhow::Result;
+use anyhow::{anyhow as error, Result};
+use futures::future::BoxFuture;
+use mongodb::error::{TRANSIENT_TRANSACTION_ERROR, UNKNOWN_TRANSACTION_COMMIT_RESULT};
 
+use crate::app::cache::{self, CachePolicy};
+use crate::app::transaction::{has_error_label, Transaction, MAX_TRANSACTION_ATTEMPTS};
 use crate::app::user::user_dao::UserDao;
+use crate::app::user::User;
+use crate::auth::refresh_token::RefreshTokenStore;
+use crate::config::AppConfig;
 
 use super::DaoObj;
 
 pub struct ApplicationDao {
+    fac: Arc<ApplicationFactory>,
     pub user: Arc<UserDao>,
+    /// `user` behind the read-through cache when it is cacheable and
+    /// `CACHE_TTL_USER` is set. `UserDao` is not, users hold password hashes.
+    pub cached_user: Arc<dyn DaoObj<User>>,
+    pub refresh_token: Arc<RefreshTokenStore>,
 }
 
 impl ApplicationDao {
     pub async fn new(fac: Arc<ApplicationFactory>) -> Result<Self> {
-        let user = UserDao::new(fac.clone())?;
+        let user = Arc::new(UserDao::new(fac.clone())?);
         user.init().await?;
 
+        let cache_policy = CachePolicy::from_config(&fac.config, user.get_collection_name());
+        let cached_user = cache::cached::<User>(user.clone(), cache_policy);
+
+        let refresh_expiry_seconds = fac.config.token_expiry_days * 24 * 60 * 60;
+        let refresh_token = RefreshTokenStore::new(fac.clone(), refresh_expiry_seconds);
+
         Ok(Self {
-            user: Arc::new(user),
+            fac,
+            user,
+            cached_user,
+            refresh_token: Arc::new(refresh_token),
         })
     }
+
+    pub fn config(&self) -> Arc<AppConfig> {
+        self.fac.config.clone()
+    }
+
+    /// Runs `f` as a unit of work inside a Mongo transaction.
+    ///
+    /// Commits when `f` returns `Ok` and aborts otherwise. Transient transaction
+    /// errors (write conflicts, elections) rerun `f` from the start, so it must
+    /// not have side effects outside the transaction. Requires the Mongo storage
+    /// backend on a replica set, other backends fail before `f` runs.
+    ///
+    /// ```ignore
+    /// app_dao
+    ///     .transaction(|tx, dao| {
+    ///         let user = user.clone();
+    ///         Box::pin(async move {
+    ///             let user = tx.create(dao.user.as_ref(), user).await?;
+    ///             tx.delete(dao.user.as_ref(), &old_id).await?;
+    ///             Ok(user)
+    ///         })
+    ///     })
+    ///     .await?;
+    /// ```
+    pub async fn transaction<R, F>(&self, f: F) -> Result<R>
+    where
+        R: Send,
+        F: for<'t> Fn(&'t mut Transaction, &'t ApplicationDao) -> BoxFuture<'t, Result<R>>
+            + Send
+            + Sync,
+    {
+        let mut tx = Transaction::new(self.fac.store.start_session().await?);
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            tx.session().start_transaction(None).await?;
+
+            let result = match f(&mut tx, self).await {
+                Ok(v) => v,
+                Err(e) => {
+                    if let Err(abort) = tx.session().abort_transaction().await {
+                        log::error!("Transaction abort error: {}", abort.to_string());
+                    }
+
+                    if attempt < MAX_TRANSACTION_ATTEMPTS
+                        && has_error_label(&e, TRANSIENT_TRANSACTION_ERROR)
+                    {
+                        log::warn!("Retrying transaction after transient error: {}", e);
+                        continue;
+                    }
+                    return Err(e);
+                }
+            };
+
+            let mut commit_attempt = 0;
+            let commit = loop {
+                commit_attempt += 1;
+                match tx.session().commit_transaction().await {
+                    Err(e)
+                        if commit_attempt < MAX_TRANSACTION_ATTEMPTS
+                            && e.contains_label(UNKNOWN_TRANSACTION_COMMIT_RESULT) =>
+                    {
+                        continue
+                    }
+                    other => break other,
+                }
+            };
+
+            match commit {
+                Ok(()) => return Ok(result),
+                Err(e)
+                    if attempt < MAX_TRANSACTION_ATTEMPTS
+                        && e.contains_label(TRANSIENT_TRANSACTION_ERROR) =>
+                {
+                    log::warn!("Retrying transaction after transient commit error: {}", e);
+                    continue;
+                }
+                Err(e) => return Err(error!("Transaction commit failed: {}", e)),
+            }
+        }
+    }
 }
 
 pub static APPLICATION_DAO: OnceLock<Arc<ApplicationDao>> = OnceLock::new();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn transactions_fail_cleanly_without_mongo() {
+        let fac = Arc::new(ApplicationFactory::in_memory());
+        let app_dao = ApplicationDao::new(fac).await.unwrap();
+
+        let err = app_dao
+            .transaction(|_, _| Box::pin(async { Ok(()) }))
+            .await
+            .unwrap_err();
+
+        assert!(
+            err.to_string().contains("only supported by the mongo"),
+            "{}",
+            err
+        );
+    }
+}
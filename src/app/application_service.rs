@@ -14,7 +14,12 @@ pub struct ApplicationService {
 impl ApplicationService {
     pub fn new(app_dao: Arc<ApplicationDao>) -> Result<Self> {
         Ok(Self {
-            user: Arc::new(UserService::new(app_dao.user.clone())),
+            user: Arc::new(UserService::new(
+                app_dao.user.clone(),
+                app_dao.cached_user.clone(),
+                app_dao.refresh_token.clone(),
+                app_dao.config(),
+            )),
         })
     }
 }
@@ -0,0 +1,621 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use mongodb::bson::{Bson, Document};
+use mongodb::options::FindOptions;
+use mongodb::ClientSession;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::app::dao::DaoObj;
+use crate::app::dto::DTO;
+use crate::app::index::IndexSpec;
+use crate::app::page::{Page, PageRequest};
+use crate::application_factory::ApplicationFactory;
+use crate::config::AppConfig;
+
+/// How long a reader waits between checks while another node fills a key.
+const LOCK_POLL: Duration = Duration::from_millis(25);
+
+/// Read-through cache settings of one collection.
+#[derive(Debug, Clone)]
+pub struct CachePolicy {
+    /// Lifetime of cached items and pages.
+    pub ttl: Duration,
+    /// Lifetime of the fill lock, bounding how long a crashed loader blocks others.
+    pub lock_ttl: Duration,
+    /// How long readers wait for another loader before going to the DAO themselves.
+    pub lock_wait: Duration,
+}
+
+impl CachePolicy {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            lock_ttl: Duration::from_secs(5),
+            lock_wait: Duration::from_secs(1),
+        }
+    }
+
+    /// Policy from the `cache_ttl_<collection>` setting in seconds. Unset or `0`
+    /// disables caching.
+    pub fn from_config(config: &AppConfig, collection: &str) -> Option<Self> {
+        config
+            .cache_ttl(collection)
+            .map(|ttl| Self::new(Duration::from_secs(ttl)))
+    }
+}
+
+/// Wraps `dao` in a [`CachedDao`] when `policy` is set and the DAO opts in
+/// through [`DaoObj::cacheable`].
+pub fn cached<T>(dao: Arc<dyn DaoObj<T>>, policy: Option<CachePolicy>) -> Arc<dyn DaoObj<T>>
+where
+    T: Clone + Serialize + DeserializeOwned + 'static + Send + Sync,
+{
+    match policy {
+        Some(policy) if dao.cacheable() => Arc::new(CachedDao::new(dao, policy)),
+        Some(_) => {
+            log::warn!(
+                "`{}` is not cacheable, ignoring its cache TTL",
+                dao.get_collection_name()
+            );
+            dao
+        }
+        None => dao,
+    }
+}
+
+/// Key value storage behind [`CachedDao`]. Values expire after `ttl`.
+#[async_trait]
+pub trait CacheBackend: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<String>>;
+
+    async fn set(&self, key: &str, value: &str, ttl: Duration) -> Result<()>;
+
+    /// Sets `key` unless it exists. Returns whether it was set.
+    async fn set_nx(&self, key: &str, ttl: Duration) -> Result<bool>;
+
+    async fn del(&self, key: &str) -> Result<()>;
+
+    /// Increments the counter under `key`, which never expires.
+    async fn incr(&self, key: &str) -> Result<u64>;
+}
+
+pub struct RedisCacheBackend {
+    fac: Arc<ApplicationFactory>,
+}
+
+impl RedisCacheBackend {
+    pub fn new(fac: Arc<ApplicationFactory>) -> Self {
+        Self { fac }
+    }
+
+    fn connection(&self) -> Result<ConnectionManager> {
+        self.fac.redis_provider.get_connection()
+    }
+}
+
+#[async_trait]
+impl CacheBackend for RedisCacheBackend {
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.connection()?.get(key).await?)
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Duration) -> Result<()> {
+        let _: () = self
+            .connection()?
+            .set_ex(key, value, ttl.as_secs() as usize)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_nx(&self, key: &str, ttl: Duration) -> Result<bool> {
+        let set: Option<String> = redis::cmd("SET")
+            .arg(key)
+            .arg(1)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl.as_millis() as u64)
+            .query_async(&mut self.connection()?)
+            .await?;
+        Ok(set.is_some())
+    }
+
+    async fn del(&self, key: &str) -> Result<()> {
+        let _: () = self.connection()?.del(key).await?;
+        Ok(())
+    }
+
+    async fn incr(&self, key: &str) -> Result<u64> {
+        Ok(self.connection()?.incr(key, 1).await?)
+    }
+}
+
+/// Process local backend for tests and single instance setups.
+#[derive(Default)]
+pub struct MemoryCacheBackend {
+    entries: Mutex<HashMap<String, (String, Option<Instant>)>>,
+}
+
+impl MemoryCacheBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn live(&self, key: &str) -> Option<String> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some((_, Some(expires))) if *expires <= Instant::now() => {
+                entries.remove(key);
+                None
+            }
+            Some((value, _)) => Some(value.clone()),
+            None => None,
+        }
+    }
+}
+
+#[async_trait]
+impl CacheBackend for MemoryCacheBackend {
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.live(key))
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Duration) -> Result<()> {
+        self.entries.lock().unwrap().insert(
+            key.to_string(),
+            (value.to_string(), Some(Instant::now() + ttl)),
+        );
+        Ok(())
+    }
+
+    async fn set_nx(&self, key: &str, ttl: Duration) -> Result<bool> {
+        if self.live(key).is_some() {
+            return Ok(false);
+        }
+        self.set(key, "1", ttl).await?;
+        Ok(true)
+    }
+
+    async fn del(&self, key: &str) -> Result<()> {
+        self.entries.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    async fn incr(&self, key: &str) -> Result<u64> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries
+            .entry(key.to_string())
+            .or_insert(("0".to_string(), None));
+        let next = entry.0.parse::<u64>()? + 1;
+        entry.0 = next.to_string();
+        Ok(next)
+    }
+}
+
+/// Redis read-through cache around a [`DaoObj`].
+///
+/// `get` and `find` (and so `list` and `query`) are served from the cache, every
+/// other call goes to the wrapped DAO. Cached items and pages are keyed by the
+/// collection generation, which every write through this DAO bumps, so one
+/// increment retires all of them and a reader that loaded before a write can
+/// only fill a key no later reader looks at. Only one reader per key loads from
+/// the DAO at a time, the others wait for it to fill the key. Cache errors are
+/// logged and fall back to the DAO.
+///
+/// Keys:
+/// - `cache::<collection>::gen` generation, bumped on every write
+/// - `cache::<collection>::item::<gen>::<id>` a `DTO<T>`
+/// - `cache::<collection>::page::<gen>::<query>` a `Page<T>`
+/// - `<key>::lock` held while a reader fills `<key>`
+pub struct CachedDao<T> {
+    inner: Arc<dyn DaoObj<T>>,
+    policy: CachePolicy,
+    backend: Arc<dyn CacheBackend>,
+}
+
+impl<T> CachedDao<T>
+where
+    T: Clone + Serialize + DeserializeOwned + 'static + Send + Sync,
+{
+    /// Caches in the redis of `inner`'s factory.
+    pub fn new(inner: Arc<dyn DaoObj<T>>, policy: CachePolicy) -> Self {
+        let backend = Arc::new(RedisCacheBackend::new(inner.get_factory()));
+        Self::with_backend(inner, policy, backend)
+    }
+
+    pub fn with_backend(
+        inner: Arc<dyn DaoObj<T>>,
+        policy: CachePolicy,
+        backend: Arc<dyn CacheBackend>,
+    ) -> Self {
+        Self {
+            inner,
+            policy,
+            backend,
+        }
+    }
+
+    fn key(&self, suffix: &str) -> String {
+        format!("cache::{}::{}", self.inner.get_collection_name(), suffix)
+    }
+
+    async fn generation(&self) -> Result<u64> {
+        let gen = self.backend.get(&self.key("gen")).await?;
+        Ok(gen.and_then(|g| g.parse().ok()).unwrap_or(0))
+    }
+
+    /// Returns the value under the key `key_at` gives for the current
+    /// generation, loading it with `load` on a miss.
+    async fn read_through<V, F>(&self, key_at: impl FnOnce(u64) -> String, load: F) -> Result<V>
+    where
+        V: Serialize + DeserializeOwned + Send + Sync,
+        F: Future<Output = Result<V>> + Send,
+    {
+        let key = match self.generation().await {
+            Ok(gen) => key_at(gen),
+            Err(e) => {
+                log::warn!(
+                    "Cache unavailable, reading `{}` from the dao: {}",
+                    self.inner.get_collection_name(),
+                    e
+                );
+                return load.await;
+            }
+        };
+
+        let locked = match self.lookup::<V>(&key).await {
+            Ok(Lookup::Hit(v)) => return Ok(v),
+            Ok(Lookup::Miss { locked }) => locked,
+            Err(e) => {
+                log::warn!("Cache error on `{}`, reading from the dao: {}", key, e);
+                return load.await;
+            }
+        };
+
+        let value = load.await;
+        if let Err(e) = self.store(&key, &value, locked).await {
+            log::warn!("Unable to cache `{}`: {}", key, e);
+        }
+
+        value
+    }
+
+    /// Looks `key` up. On a miss takes the fill lock, or waits up to
+    /// `lock_wait` for the reader holding it.
+    async fn lookup<V: DeserializeOwned>(&self, key: &str) -> Result<Lookup<V>> {
+        if let Some(v) = self.cached_value(key).await? {
+            return Ok(Lookup::Hit(v));
+        }
+
+        let locked = self
+            .backend
+            .set_nx(&lock_key(key), self.policy.lock_ttl)
+            .await?;
+
+        if !locked {
+            let deadline = tokio::time::Instant::now() + self.policy.lock_wait;
+            while tokio::time::Instant::now() < deadline {
+                tokio::time::sleep(LOCK_POLL).await;
+                if let Some(v) = self.cached_value(key).await? {
+                    return Ok(Lookup::Hit(v));
+                }
+            }
+        }
+
+        Ok(Lookup::Miss { locked })
+    }
+
+    async fn cached_value<V: DeserializeOwned>(&self, key: &str) -> Result<Option<V>> {
+        match self.backend.get(key).await? {
+            // Unreadable entries (e.g. written before a model change) count as misses.
+            Some(raw) => Ok(serde_json::from_str(&raw).ok()),
+            None => Ok(None),
+        }
+    }
+
+    /// Caches a loaded value and releases the fill lock.
+    async fn store<V: Serialize + Sync>(
+        &self,
+        key: &str,
+        value: &Result<V>,
+        locked: bool,
+    ) -> Result<()> {
+        if let Ok(value) = value {
+            let raw = serde_json::to_string(value)?;
+            self.backend.set(key, &raw, self.policy.ttl).await?;
+        }
+
+        if locked {
+            self.backend.del(&lock_key(key)).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Retires every cached item and page of the collection.
+    async fn invalidate(&self) {
+        if let Err(e) = self.backend.incr(&self.key("gen")).await {
+            log::warn!(
+                "Unable to invalidate cache of `{}`: {}",
+                self.inner.get_collection_name(),
+                e
+            );
+        }
+    }
+}
+
+enum Lookup<V> {
+    Hit(V),
+    Miss { locked: bool },
+}
+
+fn lock_key(key: &str) -> String {
+    format!("{}::lock", key)
+}
+
+#[async_trait]
+impl<T> DaoObj<T> for CachedDao<T>
+where
+    T: Clone + Serialize + DeserializeOwned + 'static + Send + Sync,
+{
+    fn get_factory(&self) -> Arc<ApplicationFactory> {
+        self.inner.get_factory()
+    }
+
+    fn get_collection_name(&self) -> &str {
+        self.inner.get_collection_name()
+    }
+
+    fn indexes(&self) -> Vec<IndexSpec> {
+        self.inner.indexes()
+    }
+
+    async fn init(&self) -> Result<()> {
+        self.inner.init().await
+    }
+
+    fn soft_delete(&self) -> bool {
+        self.inner.soft_delete()
+    }
+
+    fn reserved_fields(&self) -> Vec<&'static str> {
+        self.inner.reserved_fields()
+    }
+
+    fn patchable_fields(&self) -> Option<Vec<&'static str>> {
+        self.inner.patchable_fields()
+    }
+
+    async fn create(&self, data: DTO<T>) -> Result<DTO<T>> {
+        let result = self.inner.create(data).await?;
+        self.invalidate().await;
+        Ok(result)
+    }
+
+    async fn get(&self, id: &str) -> Result<DTO<T>> {
+        let key_at = |gen| self.key(&format!("item::{}::{}", gen, id));
+        self.read_through(key_at, self.inner.get(id)).await
+    }
+
+    async fn update(&self, data: DTO<T>) -> Result<DTO<T>> {
+        let result = self.inner.update(data).await;
+        self.invalidate().await;
+        result
+    }
+
+    async fn patch(
+        &self,
+        id: &str,
+        changes: Document,
+        expected_version: Option<i64>,
+    ) -> Result<DTO<T>> {
+        let result = self.inner.patch(id, changes, expected_version).await;
+        self.invalidate().await;
+        result
+    }
+
+    async fn find(
+        &self,
+        query: Document,
+        page: &PageRequest,
+        options: Option<FindOptions>,
+    ) -> Result<Page<T>> {
+        let sort = options
+            .as_ref()
+            .and_then(|o| o.sort.clone())
+            .unwrap_or_default();
+        let request = Bson::Document(mongodb::bson::doc! {
+            "query": query.clone(),
+            "sort": sort,
+            "page": serde_json::to_string(page)?,
+        })
+        .into_relaxed_extjson();
+        let key_at = |gen| self.key(&format!("page::{}::{}", gen, request));
+
+        self.read_through(key_at, self.inner.find(query, page, options))
+            .await
+    }
+
+    async fn find_unscoped(
+        &self,
+        query: Document,
+        page: &PageRequest,
+        options: Option<FindOptions>,
+    ) -> Result<Page<T>> {
+        self.inner.find_unscoped(query, page, options).await
+    }
+
+    async fn delete(&self, id: &str) -> Result<()> {
+        let result = self.inner.delete(id).await;
+        self.invalidate().await;
+        result
+    }
+
+    async fn list_trashed(&self, page: &PageRequest) -> Result<Page<T>> {
+        self.inner.list_trashed(page).await
+    }
+
+    async fn restore(&self, id: &str) -> Result<DTO<T>> {
+        let result = self.inner.restore(id).await;
+        self.invalidate().await;
+        result
+    }
+
+    async fn purge(&self, id: &str) -> Result<()> {
+        let result = self.inner.purge(id).await;
+        self.invalidate().await;
+        result
+    }
+
+    async fn create_with_session(
+        &self,
+        session: &mut ClientSession,
+        data: DTO<T>,
+    ) -> Result<DTO<T>> {
+        let result = self.inner.create_with_session(session, data).await?;
+        self.invalidate().await;
+        Ok(result)
+    }
+
+    async fn get_with_session(&self, session: &mut ClientSession, id: &str) -> Result<DTO<T>> {
+        self.inner.get_with_session(session, id).await
+    }
+
+    /// Invalidates before the transaction commits, so a read in between can
+    /// cache the old document until the TTL passes.
+    async fn update_with_session(
+        &self,
+        session: &mut ClientSession,
+        data: DTO<T>,
+    ) -> Result<DTO<T>> {
+        let result = self.inner.update_with_session(session, data).await;
+        self.invalidate().await;
+        result
+    }
+
+    async fn delete_with_session(&self, session: &mut ClientSession, id: &str) -> Result<()> {
+        let result = self.inner.delete_with_session(session, id).await;
+        self.invalidate().await;
+        result
+    }
+
+    async fn find_with_session(
+        &self,
+        session: &mut ClientSession,
+        query: Document,
+        options: Option<FindOptions>,
+    ) -> Result<Vec<DTO<T>>> {
+        self.inner.find_with_session(session, query, options).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::user::user_dao::UserDao;
+    use crate::app::user::User;
+    use mongodb::bson::doc;
+
+    async fn user_dao() -> Arc<UserDao> {
+        let dao = UserDao::new(Arc::new(ApplicationFactory::in_memory())).unwrap();
+        dao.init().await.unwrap();
+        Arc::new(dao)
+    }
+
+    fn cached_dao(inner: Arc<UserDao>) -> CachedDao<User> {
+        let policy = CachePolicy::new(Duration::from_secs(60));
+        CachedDao::with_backend(inner, policy, Arc::new(MemoryCacheBackend::new()))
+    }
+
+    async fn create(dao: &dyn DaoObj<User>, email: &str) -> String {
+        let user = User::new(email, "secret").await.unwrap();
+        let user = dao.create(DTO::new(user)).await.unwrap();
+        user.id.unwrap()
+    }
+
+    /// Changes the stored email behind the cache's back.
+    async fn rename_in_store(inner: &UserDao, id: &str, email: &str) {
+        inner.patch(id, doc! {"email": email}, None).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn get_is_served_from_the_cache_after_a_miss() {
+        let inner = user_dao().await;
+        let cached = cached_dao(inner.clone());
+        let id = create(&cached, "a@example.com").await;
+
+        assert_eq!(cached.get(&id).await.unwrap().email(), "a@example.com");
+        rename_in_store(&inner, &id, "b@example.com").await;
+
+        assert_eq!(cached.get(&id).await.unwrap().email(), "a@example.com");
+    }
+
+    #[tokio::test]
+    async fn writes_retire_cached_items_and_pages() {
+        let inner = user_dao().await;
+        let cached = cached_dao(inner.clone());
+        let id = create(&cached, "a@example.com").await;
+        let page = PageRequest::default();
+
+        cached.get(&id).await.unwrap();
+        assert_eq!(cached.find(doc! {}, &page, None).await.unwrap().total, 1);
+
+        create(&cached, "b@example.com").await;
+        assert_eq!(cached.find(doc! {}, &page, None).await.unwrap().total, 2);
+
+        // The create retired the cached item too. Cache it again, then a write
+        // behind the cache's back is only seen after the next write through it.
+        cached.get(&id).await.unwrap();
+        rename_in_store(&inner, &id, "c@example.com").await;
+        assert_eq!(cached.get(&id).await.unwrap().email(), "a@example.com");
+        cached.delete(&id).await.unwrap();
+
+        let err = cached.get(&id).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(crate::app::dao::DaoError::NotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn errors_are_not_cached() {
+        let inner = user_dao().await;
+        let cached = cached_dao(inner.clone());
+
+        assert!(cached.get("missing").await.is_err());
+        let lock = cached.key("item::0::missing::lock");
+        assert_eq!(cached.backend.get(&lock).await.unwrap(), None);
+        assert_eq!(
+            cached
+                .backend
+                .get(&cached.key("item::0::missing"))
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_the_dao_without_redis() {
+        // The in memory factory never connects to redis.
+        let inner = user_dao().await;
+        let cached = CachedDao::new(inner.clone(), CachePolicy::new(Duration::from_secs(60)));
+
+        let id = create(&cached, "a@example.com").await;
+        assert_eq!(cached.get(&id).await.unwrap().email(), "a@example.com");
+
+        rename_in_store(&inner, &id, "b@example.com").await;
+        assert_eq!(cached.get(&id).await.unwrap().email(), "b@example.com");
+        let page = cached
+            .find(doc! {}, &PageRequest::default(), None)
+            .await
+            .unwrap();
+        assert_eq!(page.total, 1);
+    }
+}
@@ -1,4 +1,6 @@
 #[derive(strum::Display)]
 pub enum Collections {
     User,
+    Migrations,
+    MigrationLock,
 }
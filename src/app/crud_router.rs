@@ -0,0 +1,291 @@
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use axum::{
+    extract::{Json, Path, Query, State},
+    http::{
+        header::{ETAG, IF_MATCH},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+    Router,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use validator::Validate;
+
+use crate::app::dto::DTO;
+use crate::app::page::PageRequest;
+use crate::app::service::Service;
+use crate::app::view::{View, ViewPage, Viewer};
+use crate::auth::permissions::require_permission;
+use crate::server::ServerState;
+use crate::server_errors::{AppError, ServerError};
+use crate::validation::ValidatedJson;
+
+/// `ETag` for a document, its quoted version.
+fn etag<T>(data: &DTO<T>) -> Option<HeaderValue> {
+    data.version
+        .and_then(|v| HeaderValue::from_str(&format!("\"{}\"", v)).ok())
+}
+
+/// `data` rendered as `V` for `viewer`, with its `ETag`.
+fn with_etag<T, V: View<T>>(status: StatusCode, data: &DTO<T>, viewer: &Viewer) -> Response {
+    let mut headers = HeaderMap::new();
+    if let Some(etag) = etag(data) {
+        headers.insert(ETAG, etag);
+    }
+
+    (status, headers, Json(V::view(data, viewer))).into_response()
+}
+
+/// Expected version from an `If-Match` header. `*` or no header skips the check.
+fn if_match(headers: &HeaderMap) -> Result<Option<i64>, ServerError> {
+    let value = match headers.get(IF_MATCH) {
+        Some(v) => v
+            .to_str()
+            .map_err(|_| ServerError::BadRequest("Invalid If-Match header".to_string()))?
+            .trim(),
+        None => return Ok(None),
+    };
+
+    if value == "*" {
+        return Ok(None);
+    }
+
+    value
+        .trim_start_matches("W/")
+        .trim_matches('"')
+        .parse()
+        .map(Some)
+        .map_err(|_| ServerError::BadRequest(format!("Invalid If-Match `{}`", value)))
+}
+
+/// Permission each [`crud_router`] route requires, checked with
+/// [`require_permission`].
+///
+/// ```ignore
+/// CrudPermissions {
+///     create: "todo:create",
+///     list: "todo:list",
+///     read: "todo:read",
+///     update: "todo:update",
+///     delete: "todo:delete",
+/// }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct CrudPermissions {
+    /// `POST /`
+    pub create: &'static str,
+    /// `GET /`
+    pub list: &'static str,
+    /// `GET /:id`
+    pub read: &'static str,
+    /// `PUT /:id` and `PATCH /:id`
+    pub update: &'static str,
+    /// `DELETE /:id`
+    pub delete: &'static str,
+}
+
+struct Crud<T, V, S> {
+    _marker: PhantomData<fn(T, V, S)>,
+}
+
+impl<T, V, S> Crud<T, V, S>
+where
+    T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static,
+    V: View<T> + Send + 'static,
+    S: Service<T> + Send + Sync + 'static,
+{
+    async fn create(
+        State(service): State<Arc<S>>,
+        viewer: Viewer,
+        ValidatedJson(payload): ValidatedJson<T>,
+    ) -> Result<Response, AppError>
+    where
+        T: Validate,
+    {
+        let result = service.create(payload).await?;
+
+        Ok(with_etag::<T, V>(StatusCode::CREATED, &result, &viewer))
+    }
+
+    async fn list(
+        State(service): State<Arc<S>>,
+        viewer: Viewer,
+        Query(page): Query<PageRequest>,
+        Query(params): Query<Vec<(String, String)>>,
+    ) -> Result<Json<ViewPage<V>>, AppError> {
+        let query = service.query_spec().parse(&params)?;
+        let result = service.find(&query, &page).await?;
+
+        Ok(Json(result.view(&viewer)))
+    }
+
+    async fn get(
+        State(service): State<Arc<S>>,
+        viewer: Viewer,
+        Path(id): Path<String>,
+    ) -> Result<Response, AppError> {
+        let result = service.get(&id).await?;
+
+        Ok(with_etag::<T, V>(StatusCode::OK, &result, &viewer))
+    }
+
+    async fn replace(
+        State(service): State<Arc<S>>,
+        viewer: Viewer,
+        Path(id): Path<String>,
+        headers: HeaderMap,
+        ValidatedJson(payload): ValidatedJson<T>,
+    ) -> Result<Response, AppError>
+    where
+        T: Validate,
+    {
+        let expected_version = if_match(&headers)?;
+
+        let mut existing = service.get(&id).await?;
+        existing.data = payload;
+        if expected_version.is_some() {
+            existing.version = expected_version;
+        }
+
+        let result = service.update(existing).await?;
+
+        Ok(with_etag::<T, V>(StatusCode::OK, &result, &viewer))
+    }
+
+    async fn patch(
+        State(service): State<Arc<S>>,
+        viewer: Viewer,
+        Path(id): Path<String>,
+        headers: HeaderMap,
+        Json(payload): Json<Value>,
+    ) -> Result<Response, AppError> {
+        let expected_version = if_match(&headers)?;
+        let result = service.patch(&id, payload, expected_version).await?;
+
+        Ok(with_etag::<T, V>(StatusCode::OK, &result, &viewer))
+    }
+
+    async fn delete(
+        State(service): State<Arc<S>>,
+        Path(id): Path<String>,
+    ) -> Result<StatusCode, AppError> {
+        service.delete(&id).await?;
+
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    async fn list_trashed(
+        State(service): State<Arc<S>>,
+        viewer: Viewer,
+        Query(page): Query<PageRequest>,
+    ) -> Result<Json<ViewPage<V>>, AppError> {
+        let result = service.list_trashed(&page).await?;
+
+        Ok(Json(result.view(&viewer)))
+    }
+
+    async fn restore(
+        State(service): State<Arc<S>>,
+        viewer: Viewer,
+        Path(id): Path<String>,
+    ) -> Result<Response, AppError> {
+        let result = service.restore(&id).await?;
+
+        Ok(with_etag::<T, V>(StatusCode::OK, &result, &viewer))
+    }
+
+    async fn purge(
+        State(service): State<Arc<S>>,
+        Path(id): Path<String>,
+    ) -> Result<StatusCode, AppError> {
+        service.purge(&id).await?;
+
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
+
+/// REST routes for any [`Service`], each guarded by its entry in `permissions`.
+/// Documents are rendered as `V` for the calling [`Viewer`]:
+///
+/// - `POST /` create from a `T` body
+/// - `GET /?page=1&page_size=10` or `GET /?after=<cursor>` list, filtered and
+///   sorted by the fields in [`Service::query_spec`]
+/// - `GET /:id` get
+/// - `PUT /:id` replace with a `T` body
+/// - `PATCH /:id` partial update of top level fields, `null` removes a field
+/// - `DELETE /:id` delete
+///
+/// Single document responses carry an `ETag` with the document version. Send it
+/// back in `If-Match` on `PUT`/`PATCH` to get a 409 instead of overwriting a
+/// concurrent change.
+///
+/// ```ignore
+/// .nest("/todo", crud_router::<Todo, TodoView, _>(application_service.todo.clone(), TODO_PERMISSIONS))
+/// ```
+///
+/// `POST` and `PUT` bodies are checked with the [`Validate`] rules of `T` and
+/// rejected with 422 listing the invalid fields. `PATCH` bodies are not
+/// validated. Models that transform input in their constructor (like `User`
+/// hashing its password) should keep hand written create handlers.
+pub fn crud_router<T, V, S>(
+    service: Arc<S>,
+    permissions: CrudPermissions,
+) -> Router<Arc<ServerState>>
+where
+    T: Clone + Serialize + DeserializeOwned + Validate + Send + Sync + 'static,
+    V: View<T> + Send + 'static,
+    S: Service<T> + Send + Sync + 'static,
+{
+    Router::new()
+        .route(
+            "/",
+            get(Crud::<T, V, S>::list).route_layer(require_permission(permissions.list)),
+        )
+        .route(
+            "/",
+            post(Crud::<T, V, S>::create).route_layer(require_permission(permissions.create)),
+        )
+        .route(
+            "/:id",
+            get(Crud::<T, V, S>::get).route_layer(require_permission(permissions.read)),
+        )
+        .route(
+            "/:id",
+            put(Crud::<T, V, S>::replace)
+                .patch(Crud::<T, V, S>::patch)
+                .route_layer(require_permission(permissions.update)),
+        )
+        .route(
+            "/:id",
+            delete(Crud::<T, V, S>::delete).route_layer(require_permission(permissions.delete)),
+        )
+        .with_state(service)
+}
+
+/// Trash routes for a soft deleting [`Service`], guarded by `permission` and
+/// rendering documents as `V`:
+///
+/// - `GET /` list trashed documents
+/// - `POST /:id/restore` restore
+/// - `DELETE /:id` purge permanently
+///
+/// ```ignore
+/// .nest("/todo/trash", trash_router::<Todo, TodoView, _>(service, "todo:trash"))
+/// ```
+pub fn trash_router<T, V, S>(service: Arc<S>, permission: &'static str) -> Router<Arc<ServerState>>
+where
+    T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static,
+    V: View<T> + Send + 'static,
+    S: Service<T> + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/", get(Crud::<T, V, S>::list_trashed))
+        .route("/:id", delete(Crud::<T, V, S>::purge))
+        .route("/:id/restore", post(Crud::<T, V, S>::restore))
+        .route_layer(require_permission(permission))
+        .with_state(service)
+}
@@ -1,18 +1,23 @@
 use crate::application_factory::ApplicationFactory;
-use std::{convert::From, fmt::Debug};
 
 use anyhow::{anyhow as error, Result};
-use futures::StreamExt;
-use mongodb::bson::{doc, oid::ObjectId, Document};
+use mongodb::bson::{doc, Bson, Document};
 use mongodb::options::FindOptions;
+use mongodb::ClientSession;
 
 use std::sync::Arc;
 
 use serde::{de::DeserializeOwned, Serialize};
-use std::str::FromStr;
 
-use crate::app::dto::DTO;
+use crate::app::dto::{id_from_bson, id_to_bson, DTO};
+use crate::app::index::IndexSpec;
+use crate::app::page::{
+    cursor_keys, encode_cursor, keyset_filter, reverse_sort, Page, PageMode, PageRequest,
+};
+use crate::app::query::FindQuery;
+use crate::persistence::document_store::DocumentStore;
 use async_trait::async_trait;
+use thiserror::Error;
 
 //pub struct DaoObj<T> {
 //    factory: Option<Arc<ApplicationFactory>>,
@@ -21,6 +26,23 @@ use async_trait::async_trait;
 //    collection: mongodb::Collection<DTO<T>>,
 //}
 
+#[derive(Debug, Error)]
+pub enum DaoError {
+    #[error("Item `{id}` was modified by another request. Expected version {expected}")]
+    Conflict { id: String, expected: i64 },
+
+    /// A write violated a unique index. Holds the store's message.
+    #[error("Duplicate key: {0}")]
+    DuplicateKey(String),
+
+    #[error("Could not find item with id `{id}`")]
+    NotFound { id: String },
+
+    /// The request cannot be applied, e.g. a patch of a reserved field.
+    #[error("{0}")]
+    Invalid(String),
+}
+
 #[async_trait]
 pub trait DaoObj<T>: Send + Sync
 where
@@ -30,10 +52,76 @@ where
 
     fn get_collection_name(&self) -> &str;
 
+    /// Indexes the collection should have. Reconciled by `init` at startup.
+    /// DAOs declaring none leave the collection's indexes alone.
+    fn indexes(&self) -> Vec<IndexSpec> {
+        vec![]
+    }
+
+    /// Opt in to `init` dropping indexes that are not in [`DaoObj::indexes`].
+    /// Off by default so indexes added by hand or by other services survive.
+    fn drop_undeclared_indexes(&self) -> bool {
+        false
+    }
+
     async fn init(&self) -> Result<()> {
-        Ok(())
+        let specs = self.indexes();
+        if specs.is_empty() {
+            return Ok(());
+        }
+
+        self.get_store()
+            .sync_indexes(
+                self.get_collection_name(),
+                &specs,
+                self.drop_undeclared_indexes(),
+            )
+            .await
+    }
+
+    /// Opt in to `cache::cached`. Cached entries hold the whole `DTO<T>` in
+    /// redis, so leave this off for documents carrying secrets like password
+    /// hashes.
+    fn cacheable(&self) -> bool {
+        false
+    }
+
+    /// Opt in to soft deletes. `delete` then only sets `deleted_at` and the
+    /// document is hidden from `get`, `list` and `find` until restored or purged.
+    fn soft_delete(&self) -> bool {
+        false
+    }
+
+    /// Restricts `query` to documents that are not in the trash.
+    fn live_filter(&self, query: Document) -> Document {
+        if !self.soft_delete() {
+            return query;
+        }
+
+        if query.is_empty() {
+            doc! {"deleted_at": null}
+        } else {
+            doc! {"$and": [query, {"deleted_at": null}]}
+        }
     }
 
+    /// Backend used by every non session operation.
+    fn get_store(&self) -> Arc<dyn DocumentStore> {
+        self.get_factory().store.clone()
+    }
+
+    /// Reads a stored document as `DTO<T>`.
+    fn decode(&self, doc: Document) -> Result<DTO<T>> {
+        mongodb::bson::from_document(doc).map_err(|e| {
+            error!(
+                "Unable to read document from `{}`: {}",
+                self.get_collection_name(),
+                e
+            )
+        })
+    }
+
+    /// Raw Mongo collection, used by the `*_with_session` operations.
     fn get_collection(&self) -> Result<mongodb::Collection<DTO<T>>> {
         let col = self
             .get_factory()
@@ -45,98 +133,490 @@ where
     }
 
     async fn create(&self, mut data: DTO<T>) -> Result<DTO<T>> {
-        let col = self.get_collection()?;
-        //let item = Output::from(self);
-
-        //col.insert_one(item.clone(), None).await?;
+        let doc = mongodb::bson::to_document(&data)?;
 
-        let res = col.insert_one(data.clone(), None).await?;
-        let id = res
-            .inserted_id
-            .as_object_id()
-            .ok_or(error!("Unable to convert to object id"))?
-            .to_string();
+        let inserted_id = self
+            .get_store()
+            .insert_one(self.get_collection_name(), doc)
+            .await?;
+        let id = id_from_bson(&inserted_id).ok_or(error!("Unsupported id `{}`", inserted_id))?;
         data.id = Some(id);
 
         Ok(data)
     }
     async fn get(&self, id: &str) -> Result<DTO<T>> {
-        let col = self.get_collection()?;
-        let oid = ObjectId::from_str(id)?;
-        let mut res = col.find(doc! {"_id": oid}, None).await?;
-        let result = res
-            .next()
-            .await
-            .ok_or(error!("Could not find item with id `{}`", id))??;
+        let oid = id_to_bson(id);
+        let result = self
+            .get_store()
+            .find_one(
+                self.get_collection_name(),
+                self.live_filter(doc! {"_id": oid}),
+            )
+            .await?
+            .ok_or(DaoError::NotFound { id: id.to_string() })?;
 
-        Ok(result)
+        self.decode(result)
     }
 
+    /// Fields reserved for the framework. They are never written by `update` or `patch`.
+    fn reserved_fields(&self) -> Vec<&'static str> {
+        vec!["_id", "created_at", "updated_at", "version", "deleted_at"]
+    }
+
+    /// Top level fields `patch` may change. `None` allows every field of `T`.
+    fn patchable_fields(&self) -> Option<Vec<&'static str>> {
+        None
+    }
+
+    /// Works out why an update matched nothing: the document is gone or its
+    /// version moved on. Reads through `session` when the update ran in one.
+    async fn update_miss(
+        &self,
+        session: Option<&mut ClientSession>,
+        id: &str,
+        expected: Option<i64>,
+    ) -> anyhow::Error {
+        let store = self.get_store();
+        let filter = self.live_filter(doc! {"_id": id_to_bson(id)});
+        let exists = match session {
+            Some(session) => {
+                store
+                    .count_with_session(session, self.get_collection_name(), filter)
+                    .await
+            }
+            None => store.count(self.get_collection_name(), filter).await,
+        };
+
+        match (exists, expected) {
+            (Ok(n), Some(expected)) if n > 0 => DaoError::Conflict {
+                id: id.to_string(),
+                expected,
+            }
+            .into(),
+            (Err(e), _) => e,
+            _ => DaoError::NotFound { id: id.to_string() }.into(),
+        }
+    }
+
+    /// Filter and update document used by `update` and `update_with_session`.
+    fn update_parts(&self, data: &DTO<T>) -> Result<(String, Document, Document)> {
+        let id = data.id.clone().ok_or(DaoError::Invalid(
+            "Id on object not found for update".to_string(),
+        ))?;
+
+        let mut doc = mongodb::bson::to_document(data)?;
+        for field in self.reserved_fields() {
+            doc.remove(field);
+        }
+        doc.insert("updated_at", mongodb::bson::to_bson(&chrono::Utc::now())?);
+
+        let mut filter = self.live_filter(doc! {"_id": id_to_bson(&id)});
+        if let Some(version) = data.version {
+            filter.insert("version", version);
+        }
+
+        Ok((id, filter, doc! {"$set": doc, "$inc": {"version": 1i64}}))
+    }
+
+    /// Replaces the stored document with `data`, stamping `updated_at` and
+    /// incrementing `version`. When `data.version` is set the update only applies
+    /// if the stored version still matches, otherwise [`DaoError::Conflict`] is returned.
     async fn update(&self, data: DTO<T>) -> Result<DTO<T>> {
-        let col = self.get_collection()?;
-        let id = data
-            .id
-            .clone()
-            .ok_or(error!("Id on object not found for update"))?;
-        let doc = mongodb::bson::to_bson(&data)?;
-        let doc = doc
-            .as_document()
-            .ok_or(error!("Unable to convert bson to document"))?;
-        col.update_one(doc! {"_id": id}, doc! {"$set": doc}, None)
+        let (id, filter, update) = self.update_parts(&data)?;
+
+        let result = self
+            .get_store()
+            .find_one_and_update(self.get_collection_name(), filter, update)
             .await?;
 
-        Ok(data)
+        match result {
+            Some(v) => self.decode(v),
+            None => Err(self.update_miss(None, &id, data.version).await),
+        }
     }
 
-    async fn list(&self, page: u64, page_size: i64) -> Result<Vec<DTO<T>>> {
-        let col = self.get_collection()?;
-        let mut opt = mongodb::options::FindOptions::default();
+    /// Partially updates a document. Fields set to null are removed.
+    ///
+    /// Only top level fields allowed by [`DaoObj::patchable_fields`] are accepted and the
+    /// result must still deserialize as `T`, otherwise nothing is written. With
+    /// `expected_version` the patch is checked like [`DaoObj::update`].
+    async fn patch(
+        &self,
+        id: &str,
+        changes: Document,
+        expected_version: Option<i64>,
+    ) -> Result<DTO<T>> {
+        let oid = id_to_bson(id);
+        let reserved = self.reserved_fields();
+        let allowed = self.patchable_fields();
+
+        let mut set = Document::new();
+        let mut unset = Document::new();
 
-        let start = (page as i64 - 1) * page_size;
-        opt.limit = Some(page_size);
-        opt.skip = Some(start as u64);
+        for (key, value) in changes {
+            if key.starts_with('$') || key.contains('.') {
+                return Err(DaoError::Invalid(format!("Invalid field name `{}`", key)).into());
+            }
 
-        let mut result = col.find(doc! {}, opt).await?;
-        let mut data: Vec<DTO<T>> = Vec::new();
-        while let Some(u) = result.next().await {
-            if let Ok(uu) = u {
-                data.push(uu);
+            let is_allowed = match &allowed {
+                Some(allowed) => allowed.contains(&key.as_str()),
+                None => true,
+            };
+            if reserved.contains(&key.as_str()) || !is_allowed {
+                return Err(DaoError::Invalid(format!("Field `{}` cannot be updated", key)).into());
+            }
+
+            if value == Bson::Null {
+                unset.insert(key, "");
+            } else {
+                set.insert(key, value);
             }
         }
-        Ok(data)
+
+        let mut current = self
+            .get_store()
+            .find_one(
+                self.get_collection_name(),
+                self.live_filter(doc! {"_id": oid.clone()}),
+            )
+            .await?
+            .ok_or(DaoError::NotFound { id: id.to_string() })?;
+        for (k, v) in set.iter() {
+            current.insert(k, v.clone());
+        }
+        for k in unset.keys() {
+            current.remove(k);
+        }
+        mongodb::bson::from_document::<DTO<T>>(current)
+            .map_err(|e| DaoError::Invalid(format!("Patch produces an invalid document: {}", e)))?;
+
+        set.insert("updated_at", mongodb::bson::to_bson(&chrono::Utc::now())?);
+        let mut update = doc! {"$set": set, "$inc": {"version": 1i64}};
+        if !unset.is_empty() {
+            update.insert("$unset", unset);
+        }
+
+        let mut filter = self.live_filter(doc! {"_id": oid});
+        if let Some(version) = expected_version {
+            filter.insert("version", version);
+        }
+
+        let result = self
+            .get_store()
+            .find_one_and_update(self.get_collection_name(), filter, update)
+            .await?;
+
+        match result {
+            Some(v) => self.decode(v),
+            None => Err(self.update_miss(None, id, expected_version).await),
+        }
     }
 
+    async fn list(&self, page: &PageRequest) -> Result<Page<T>> {
+        self.find(doc! {}, page, None).await
+    }
+
+    /// Pages through documents matching `query`, newest first unless `options`
+    /// carries a sort. Ties are broken on `_id`.
     async fn find(
         &self,
         query: Document,
-        page: u64,
-        page_size: i64,
+        page: &PageRequest,
         options: Option<FindOptions>,
-    ) -> Result<Vec<DTO<T>>> {
-        let col = self.get_collection()?;
+    ) -> Result<Page<T>> {
+        self.find_unscoped(self.live_filter(query), page, options)
+            .await
+    }
+
+    /// [`DaoObj::find`] without hiding soft deleted documents.
+    async fn find_unscoped(
+        &self,
+        query: Document,
+        page: &PageRequest,
+        options: Option<FindOptions>,
+    ) -> Result<Page<T>> {
+        let store = self.get_store();
+        let collection = self.get_collection_name();
+        let page = page.validate()?;
 
-        let mut opt = options.unwrap_or(FindOptions::default());
+        let total = store.count(collection, query.clone()).await?;
 
-        opt.sort = Some(doc! {"created_at": -1});
-        let start = (page as i64 - 1) * page_size;
-        opt.limit = Some(page_size);
-        opt.skip = Some(start as u64);
+        let mut opt = options.unwrap_or_default();
+        let mut sort = opt
+            .sort
+            .take()
+            .filter(|s| !s.is_empty())
+            .unwrap_or(doc! {"created_at": -1});
+        // Tie break on _id so pages neither overlap nor skip documents.
+        if !sort.contains_key("_id") {
+            sort.insert("_id", -1);
+        }
+
+        let (filter, fetch_sort) = match &page.mode {
+            PageMode::Offset(_) => (query.clone(), sort.clone()),
+            PageMode::After(cursor) => (
+                doc! {"$and": [query.clone(), keyset_filter(&sort, cursor, true)?]},
+                sort.clone(),
+            ),
+            // Walk backwards from the cursor, the rows are flipped back below.
+            PageMode::Before(cursor) => (
+                doc! {"$and": [query.clone(), keyset_filter(&sort, cursor, false)?]},
+                reverse_sort(&sort),
+            ),
+        };
+        opt.sort = Some(fetch_sort);
+        opt.limit = Some(page.page_size + 1);
+        opt.skip = Some(page.skip()?);
+
+        // The extra row only tells whether more follow.
+        let mut rows = store.find(collection, filter, opt).await?;
+        let has_more = rows.len() as i64 > page.page_size;
+        rows.truncate(page.page_size as usize);
+        if let PageMode::Before(_) = page.mode {
+            rows.reverse();
+        }
 
-        let mut result = col.find(query, opt).await?;
-        let mut data: Vec<DTO<T>> = Vec::new();
-        while let Some(u) = result.next().await {
-            if let Ok(uu) = u {
-                data.push(uu);
+        // Rows on the far side of an `after` cursor may have been deleted since,
+        // same for a `before` cursor. Look before handing out a cursor.
+        let edge = match page.mode {
+            PageMode::Offset(_) => None,
+            PageMode::After(_) => rows.first().map(|r| (r, false)),
+            PageMode::Before(_) => rows.last().map(|r| (r, true)),
+        };
+        let beyond_edge = match edge {
+            Some((row, forward)) => {
+                let keys = cursor_keys(row, &sort);
+                let filter = doc! {"$and": [query, keyset_filter(&sort, &keys, forward)?]};
+                let opt = FindOptions::builder().limit(1).build();
+                !store.find(collection, filter, opt).await?.is_empty()
             }
+            None => false,
+        };
+
+        let (page_number, has_prev, has_next) = match page.mode {
+            PageMode::Offset(n) => (Some(n), n > 1, has_more),
+            PageMode::After(_) => (None, beyond_edge, has_more),
+            PageMode::Before(_) => (None, has_more, beyond_edge),
+        };
+
+        let prev_cursor = match rows.first() {
+            Some(first) if has_prev => Some(encode_cursor(first, &sort)?),
+            _ => None,
+        };
+        let next_cursor = match rows.last() {
+            Some(last) if has_next => Some(encode_cursor(last, &sort)?),
+            _ => None,
+        };
+
+        let items = rows
+            .into_iter()
+            .map(|d| self.decode(d))
+            .collect::<Result<Vec<DTO<T>>>>()?;
+
+        Ok(Page {
+            items,
+            total,
+            page: page_number,
+            page_size: page.page_size,
+            next_cursor,
+            prev_cursor,
+        })
+    }
+
+    async fn query(&self, query: &FindQuery, page: &PageRequest) -> Result<Page<T>> {
+        self.find(query.filter.to_document(), page, query.find_options())
+            .await
+    }
+
+    /// Update applied by `delete` on soft deleting DAOs.
+    fn soft_delete_update(&self) -> Result<Document> {
+        let now = mongodb::bson::to_bson(&chrono::Utc::now())?;
+        Ok(doc! {
+            "$set": {"deleted_at": now.clone(), "updated_at": now},
+            "$inc": {"version": 1i64},
+        })
+    }
+
+    /// Trashes the document, or removes it on DAOs without soft deletes.
+    /// `NotFound` when there is no live document with `id`.
+    async fn delete(&self, id: &str) -> Result<()> {
+        let store = self.get_store();
+        let oid = id_to_bson(id);
+
+        let count = if self.soft_delete() {
+            store
+                .update_one(
+                    self.get_collection_name(),
+                    self.live_filter(doc! {"_id": oid}),
+                    self.soft_delete_update()?,
+                )
+                .await?
+        } else {
+            store
+                .delete_one(self.get_collection_name(), doc! {"_id": oid})
+                .await?
+        };
+
+        if count == 0 {
+            return Err(DaoError::NotFound { id: id.to_string() }.into());
+        }
+
+        Ok(())
+    }
+
+    /// Documents in the trash, most recently created first.
+    async fn list_trashed(&self, page: &PageRequest) -> Result<Page<T>> {
+        if !self.soft_delete() {
+            return Err(DaoError::Invalid(format!(
+                "`{}` does not use soft deletes",
+                self.get_collection_name()
+            ))
+            .into());
+        }
+
+        self.find_unscoped(doc! {"deleted_at": {"$ne": null}}, page, None)
+            .await
+    }
+
+    /// Moves a document out of the trash.
+    async fn restore(&self, id: &str) -> Result<DTO<T>> {
+        let oid = id_to_bson(id);
+        let now = mongodb::bson::to_bson(&chrono::Utc::now())?;
+
+        let result = self
+            .get_store()
+            .find_one_and_update(
+                self.get_collection_name(),
+                doc! {"_id": oid, "deleted_at": {"$ne": null}},
+                doc! {
+                    "$set": {"updated_at": now, "deleted_at": null},
+                    "$inc": {"version": 1i64},
+                },
+            )
+            .await?
+            .ok_or(DaoError::NotFound { id: id.to_string() })?;
+
+        self.decode(result)
+    }
+
+    /// Permanently deletes a document from the trash.
+    async fn purge(&self, id: &str) -> Result<()> {
+        let oid = id_to_bson(id);
+
+        let deleted = self
+            .get_store()
+            .delete_one(
+                self.get_collection_name(),
+                doc! {"_id": oid, "deleted_at": {"$ne": null}},
+            )
+            .await?;
+
+        if deleted == 0 {
+            return Err(DaoError::NotFound { id: id.to_string() }.into());
         }
+
+        Ok(())
+    }
+
+    async fn create_with_session(
+        &self,
+        session: &mut ClientSession,
+        mut data: DTO<T>,
+    ) -> Result<DTO<T>> {
+        let doc = mongodb::bson::to_document(&data)?;
+
+        let inserted_id = self
+            .get_store()
+            .insert_one_with_session(session, self.get_collection_name(), doc)
+            .await?;
+        let id = id_from_bson(&inserted_id).ok_or(error!("Unsupported id `{}`", inserted_id))?;
+        data.id = Some(id);
+
         Ok(data)
     }
 
-    async fn delete(&self, id: &str) -> Result<()> {
-        let col = self.get_collection()?;
-        let oid = ObjectId::from_str(id)?;
-        col.delete_one(doc! {"_id": oid}, None).await?;
+    async fn get_with_session(&self, session: &mut ClientSession, id: &str) -> Result<DTO<T>> {
+        let oid = id_to_bson(id);
+        let mut opt = FindOptions::default();
+        opt.limit = Some(1);
+
+        let result = self
+            .get_store()
+            .find_with_session(
+                session,
+                self.get_collection_name(),
+                self.live_filter(doc! {"_id": oid}),
+                opt,
+            )
+            .await?
+            .pop()
+            .ok_or(DaoError::NotFound { id: id.to_string() })?;
+
+        self.decode(result)
+    }
+
+    async fn update_with_session(
+        &self,
+        session: &mut ClientSession,
+        data: DTO<T>,
+    ) -> Result<DTO<T>> {
+        let (id, filter, update) = self.update_parts(&data)?;
+
+        let result = self
+            .get_store()
+            .find_one_and_update_with_session(session, self.get_collection_name(), filter, update)
+            .await?;
+
+        match result {
+            Some(v) => self.decode(v),
+            None => Err(self.update_miss(Some(session), &id, data.version).await),
+        }
+    }
+
+    async fn delete_with_session(&self, session: &mut ClientSession, id: &str) -> Result<()> {
+        let store = self.get_store();
+        let oid = id_to_bson(id);
+
+        let count = if self.soft_delete() {
+            store
+                .update_one_with_session(
+                    session,
+                    self.get_collection_name(),
+                    self.live_filter(doc! {"_id": oid}),
+                    self.soft_delete_update()?,
+                )
+                .await?
+        } else {
+            store
+                .delete_one_with_session(session, self.get_collection_name(), doc! {"_id": oid})
+                .await?
+        };
+
+        if count == 0 {
+            return Err(DaoError::NotFound { id: id.to_string() }.into());
+        }
 
         Ok(())
     }
+
+    /// Documents matching `query`, hiding trashed ones. Not paged, bound the
+    /// result with `options.limit` where needed.
+    async fn find_with_session(
+        &self,
+        session: &mut ClientSession,
+        query: Document,
+        options: Option<FindOptions>,
+    ) -> Result<Vec<DTO<T>>> {
+        self.get_store()
+            .find_with_session(
+                session,
+                self.get_collection_name(),
+                self.live_filter(query),
+                options.unwrap_or_default(),
+            )
+            .await?
+            .into_iter()
+            .map(|doc| self.decode(doc))
+            .collect()
+    }
 }
@@ -1,9 +1,25 @@
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::ops::{Deref, DerefMut};
 
-use bson::serde_helpers::{
-    deserialize_hex_string_from_object_id, serialize_hex_string_as_object_id,
-};
+use bson::{oid::ObjectId, Bson};
+
+/// Stored form of a DTO id. 24 character hex ids are ObjectIds, anything else
+/// (UUIDs, natural keys) is kept as a string.
+pub fn id_to_bson(id: &str) -> Bson {
+    match ObjectId::parse_str(id) {
+        Ok(oid) => Bson::ObjectId(oid),
+        Err(_) => Bson::String(id.to_string()),
+    }
+}
+
+/// String form of a stored id, the inverse of [`id_to_bson`].
+pub fn id_from_bson(id: &Bson) -> Option<String> {
+    match id {
+        Bson::ObjectId(oid) => Some(oid.to_hex()),
+        Bson::String(s) => Some(s.clone()),
+        _ => None,
+    }
+}
 
 fn convert_opt_id_to_object_id<S>(v: &Option<String>, s: S) -> std::result::Result<S::Ok, S::Error>
 where
@@ -13,7 +29,7 @@ where
         None => Err(serde::ser::Error::custom(
             "Value is none, therefore cannot be converted",
         )),
-        Some(v) => serialize_hex_string_as_object_id(&v, s),
+        Some(v) => id_to_bson(v).serialize(s),
     }
 }
 
@@ -23,8 +39,10 @@ fn convert_opt_hex_string_to_string<'de, D>(
 where
     D: Deserializer<'de>,
 {
-    let val = deserialize_hex_string_from_object_id(deserializer)?;
-    Ok(Some(val))
+    let val = Bson::deserialize(deserializer)?;
+    id_from_bson(&val)
+        .map(Some)
+        .ok_or_else(|| serde::de::Error::custom(format!("`{}` is not a valid id", val)))
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,6 +54,16 @@ pub struct DTO<T> {
     pub created_at: Option<chrono::DateTime<chrono::Utc>>,
     pub updated_at: Option<chrono::DateTime<chrono::Utc>>,
 
+    /// Incremented on every update. An update carrying a stale version is rejected
+    /// with `DaoError::Conflict`. Documents written before versioning have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<i64>,
+
+    /// Set when the document is in the trash of a soft deleting DAO. Stored as
+    /// null otherwise, which `IndexSpec::live` indexes rely on.
+    #[serde(default)]
+    pub deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+
     #[serde(flatten)]
     pub data: T,
 }
@@ -60,6 +88,8 @@ impl<T> DTO<T> {
             id: None,
             created_at: Some(chrono::Utc::now()),
             updated_at: Some(chrono::Utc::now()),
+            version: Some(0),
+            deleted_at: None,
             data,
         }
     }
@@ -0,0 +1,286 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use futures::StreamExt;
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::error::ErrorKind;
+use mongodb::options::IndexOptions;
+use mongodb::IndexModel;
+
+/// Declarative index for `DaoObj::indexes`.
+///
+/// ```ignore
+/// IndexSpec::new().asc("email").unique()
+/// IndexSpec::new().asc("email").unique().live()
+/// IndexSpec::new().asc("tenant").desc("created_at")
+/// IndexSpec::new().asc("expires_at").ttl(Duration::from_secs(0))
+/// IndexSpec::new().text("title").text("body")
+/// ```
+///
+/// Unnamed indexes get Mongo's default name (`email_1`, `tenant_1_created_at_-1`),
+/// so indexes created by hand with default names are picked up as is.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IndexSpec {
+    pub name: Option<String>,
+    pub keys: Document,
+    pub unique: bool,
+    pub sparse: bool,
+    pub expire_after: Option<Duration>,
+    pub partial_filter: Option<Document>,
+}
+
+impl IndexSpec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn asc(mut self, field: &str) -> Self {
+        self.keys.insert(field, 1);
+        self
+    }
+
+    pub fn desc(mut self, field: &str) -> Self {
+        self.keys.insert(field, -1);
+        self
+    }
+
+    pub fn text(mut self, field: &str) -> Self {
+        self.keys.insert(field, "text");
+        self
+    }
+
+    pub fn named(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    pub fn unique(mut self) -> Self {
+        self.unique = true;
+        self
+    }
+
+    pub fn sparse(mut self) -> Self {
+        self.sparse = true;
+        self
+    }
+
+    /// Documents expire `after` past the date stored in the single indexed field.
+    pub fn ttl(mut self, after: Duration) -> Self {
+        self.expire_after = Some(after);
+        self
+    }
+
+    pub fn partial(mut self, filter: Document) -> Self {
+        self.partial_filter = Some(filter);
+        self
+    }
+
+    /// Only indexes documents outside the trash of a soft deleting DAO, so a
+    /// trashed document does not keep its unique key taken. Live documents
+    /// store `deleted_at: null`, the only form of "not deleted" a Mongo
+    /// partial filter can express.
+    pub fn live(self) -> Self {
+        self.partial(doc! {"deleted_at": {"$type": "null"}})
+    }
+
+    fn is_text(&self) -> bool {
+        self.keys.values().any(|v| v.as_str() == Some("text"))
+    }
+
+    pub fn index_name(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+
+        self.keys
+            .iter()
+            .map(|(k, v)| match v {
+                Bson::String(s) => format!("{}_{}", k, s),
+                v => format!("{}_{}", k, key_direction(v).unwrap_or(1)),
+            })
+            .collect::<Vec<_>>()
+            .join("_")
+    }
+
+    pub fn to_model(&self) -> IndexModel {
+        let options = IndexOptions::builder()
+            .name(self.index_name())
+            .unique(self.unique.then_some(true))
+            .sparse(self.sparse.then_some(true))
+            .expire_after(self.expire_after)
+            .partial_filter_expression(self.partial_filter.clone())
+            .build();
+
+        IndexModel::builder()
+            .keys(self.keys.clone())
+            .options(options)
+            .build()
+    }
+
+    /// Whether an index read back from Mongo is equivalent to this spec.
+    pub fn matches(&self, existing: &IndexModel) -> bool {
+        let options = existing.options.clone().unwrap_or_default();
+
+        let keys_match = if self.is_text() {
+            // Text indexes are stored as `{_fts: "text", _ftsx: 1}` with the
+            // indexed fields listed in `weights`.
+            let mut fields: Vec<&String> = self
+                .keys
+                .iter()
+                .filter(|(_, v)| v.as_str() == Some("text"))
+                .map(|(k, _)| k)
+                .collect();
+            let mut existing_fields: Vec<&String> = options
+                .weights
+                .as_ref()
+                .map(|w| w.keys().collect())
+                .unwrap_or_default();
+            fields.sort();
+            existing_fields.sort();
+            existing.keys.contains_key("_fts") && fields == existing_fields
+        } else {
+            self.keys.len() == existing.keys.len()
+                && self
+                    .keys
+                    .iter()
+                    .zip(existing.keys.iter())
+                    .all(|((k, v), (ek, ev))| k == ek && key_direction(v) == key_direction(ev))
+        };
+
+        keys_match
+            && self.unique == options.unique.unwrap_or(false)
+            && self.sparse == options.sparse.unwrap_or(false)
+            && self.expire_after == options.expire_after
+            && self.partial_filter == options.partial_filter_expression
+    }
+}
+
+fn key_direction(v: &Bson) -> Option<i64> {
+    match v {
+        Bson::Int32(v) => Some(*v as i64),
+        Bson::Int64(v) => Some(*v),
+        Bson::Double(v) => Some(*v as i64),
+        _ => None,
+    }
+}
+
+/// `specs` plus, unless `drop_undeclared`, the `existing` specs none of them
+/// replaces by name. For stores keeping their index definitions themselves.
+pub fn reconcile(
+    existing: &[IndexSpec],
+    specs: &[IndexSpec],
+    drop_undeclared: bool,
+) -> Vec<IndexSpec> {
+    let mut result = specs.to_vec();
+    if !drop_undeclared {
+        result.extend(
+            existing
+                .iter()
+                .filter(|e| !specs.iter().any(|s| s.index_name() == e.index_name()))
+                .cloned(),
+        );
+    }
+
+    result
+}
+
+/// Suffix of the name a changed index is rebuilt under. The old index keeps
+/// serving queries and enforcing uniqueness until its replacement is complete,
+/// so rebuilds alternate between `name` and `name__next`.
+const REBUILD_SUFFIX: &str = "__next";
+
+/// Name of the spec an existing index was built for.
+fn declared_name(name: &str) -> &str {
+    name.strip_suffix(REBUILD_SUFFIX).unwrap_or(name)
+}
+
+/// IndexOptionsConflict or IndexKeySpecsConflict: Mongo refuses a second index
+/// on the same keys with other options, or a name taken by another index.
+fn is_index_conflict(e: &mongodb::error::Error) -> bool {
+    matches!(&*e.kind, ErrorKind::Command(c) if c.code == 85 || c.code == 86)
+}
+
+/// Makes the indexes on `col` match `specs`. Missing indexes are created and
+/// changed ones are rebuilt next to the old one before it is dropped. Indexes
+/// not in `specs`, which may have been made by hand or by another service, are
+/// only dropped with `drop_undeclared`. The `_id_` index is never touched.
+pub async fn sync_indexes<T>(
+    col: &mongodb::Collection<T>,
+    specs: &[IndexSpec],
+    drop_undeclared: bool,
+) -> Result<()>
+where
+    T: Send + Sync,
+{
+    let mut existing: Vec<(String, IndexModel)> = Vec::new();
+    match col.list_indexes(None).await {
+        Ok(mut cursor) => {
+            while let Some(index) = cursor.next().await {
+                let index = index?;
+                if let Some(name) = index.options.as_ref().and_then(|o| o.name.clone()) {
+                    existing.push((name, index));
+                }
+            }
+        }
+        // NamespaceNotFound: the collection does not exist yet
+        Err(e) if matches!(&*e.kind, ErrorKind::Command(c) if c.code == 26) => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    for spec in specs {
+        let name = spec.index_name();
+        let (current, stale): (Vec<_>, Vec<_>) = existing
+            .iter()
+            .filter(|(n, _)| declared_name(n) == name)
+            .partition(|(_, index)| spec.matches(index));
+        let stale: Vec<&String> = stale.into_iter().map(|(n, _)| n).collect();
+
+        if current.is_empty() {
+            let build_name = if stale.contains(&&name) {
+                format!("{}{}", name, REBUILD_SUFFIX)
+            } else {
+                name.clone()
+            };
+
+            log::info!("Creating index `{}` on `{}`", build_name, col.name());
+            let model = spec.clone().named(&build_name).to_model();
+            match col.create_index(model, None).await {
+                Ok(_) => {}
+                Err(e) if is_index_conflict(&e) && !stale.is_empty() => {
+                    // Mongo allows one index per key pattern unless they differ
+                    // in collation or partial filter, so this one can only be
+                    // replaced in place.
+                    log::warn!(
+                        "Index `{}` on `{}` cannot be built next to the old one ({}). Replacing it in place",
+                        name,
+                        col.name(),
+                        e
+                    );
+                    for old in &stale {
+                        col.drop_index(old.as_str(), None).await?;
+                    }
+                    col.create_index(spec.to_model(), None).await?;
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        for old in stale {
+            log::info!("Dropping index `{}` on `{}`", old, col.name());
+            col.drop_index(old.as_str(), None).await?;
+        }
+    }
+
+    if drop_undeclared {
+        for (name, _) in &existing {
+            let declared = specs.iter().any(|s| s.index_name() == declared_name(name));
+            if name != "_id_" && !declared {
+                log::info!("Dropping index `{}` on `{}`", name, col.name());
+                col.drop_index(name.as_str(), None).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
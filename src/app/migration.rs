@@ -0,0 +1,357 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow as error, Result};
+use async_trait::async_trait;
+use futures::StreamExt;
+use mongodb::bson::{doc, DateTime, Document};
+use mongodb::options::FindOneAndUpdateOptions;
+use serde::{Deserialize, Serialize};
+
+use crate::app::collections::Collections;
+use crate::application_factory::ApplicationFactory;
+use crate::config::StorageBackend;
+use crate::persistence::document_store::duplicate_key_message;
+
+/// Id of the single lock document in the migration lock collection.
+const LOCK_ID: &str = "migrations";
+
+/// A lock older than this is taken to be left behind by a crashed run.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// A versioned schema or data change. Versions must be unique and increasing,
+/// a timestamp like `2023101801` works well.
+#[async_trait]
+pub trait Migration: Send + Sync {
+    fn version(&self) -> u64;
+
+    fn name(&self) -> &str;
+
+    async fn up(&self, db: &mongodb::Database) -> Result<()>;
+
+    async fn down(&self, db: &mongodb::Database) -> Result<()>;
+}
+
+/// Record stored in the migrations collection for every applied migration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppliedMigration {
+    #[serde(rename = "_id")]
+    pub version: i64,
+    pub name: String,
+    pub applied_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: u64,
+    pub name: String,
+    pub applied_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+pub struct MigrationRunner {
+    fac: Arc<ApplicationFactory>,
+    migrations: Vec<Box<dyn Migration>>,
+}
+
+impl MigrationRunner {
+    pub fn new(
+        fac: Arc<ApplicationFactory>,
+        mut migrations: Vec<Box<dyn Migration>>,
+    ) -> Result<Self> {
+        // Migrations, their records and the lock all live in Mongo.
+        if fac.config.storage_backend != StorageBackend::Mongo {
+            return Err(error!(
+                "Migrations need the mongo storage backend, `{}` is configured",
+                fac.config.storage_backend
+            ));
+        }
+
+        migrations.sort_by_key(|m| m.version());
+
+        for pair in migrations.windows(2) {
+            if pair[0].version() == pair[1].version() {
+                return Err(error!(
+                    "Migrations `{}` and `{}` share version {}",
+                    pair[0].name(),
+                    pair[1].name(),
+                    pair[0].version()
+                ));
+            }
+        }
+
+        Ok(Self { fac, migrations })
+    }
+
+    fn collection(&self) -> Result<mongodb::Collection<AppliedMigration>> {
+        let col = self
+            .fac
+            .mongo_provider
+            .get_database()?
+            .collection::<AppliedMigration>(&Collections::Migrations.to_string());
+        Ok(col)
+    }
+
+    /// Takes the migration lock so concurrent `up`/`down` runs, e.g. from
+    /// several instances starting at once, don't apply the same migration
+    /// twice. Returns the owner token to release it with.
+    async fn lock(&self) -> Result<String> {
+        let col = self
+            .fac
+            .mongo_provider
+            .get_database()?
+            .collection::<Document>(&Collections::MigrationLock.to_string());
+
+        let owner = uuid::Uuid::new_v4().to_string();
+        let now = DateTime::now();
+        let expired =
+            DateTime::from_millis(now.timestamp_millis() - LOCK_TIMEOUT.as_millis() as i64);
+
+        let opt = FindOneAndUpdateOptions::builder().upsert(true).build();
+
+        // A held lock fails the filter, so the upsert tries to insert a second
+        // document with the same id and is rejected.
+        let result = col
+            .find_one_and_update(
+                doc! {
+                    "_id": LOCK_ID,
+                    "$or": [{"owner": null}, {"locked_at": {"$lt": expired}}],
+                },
+                doc! {"$set": {"owner": &owner, "locked_at": now}},
+                opt,
+            )
+            .await;
+
+        match result {
+            Ok(_) => Ok(owner),
+            Err(e) if duplicate_key_message(&e).is_some() => {
+                let held = col.find_one(doc! {"_id": LOCK_ID}, None).await?;
+                let since = held
+                    .and_then(|d| d.get_datetime("locked_at").ok().copied())
+                    .map(|at| at.to_string())
+                    .unwrap_or_default();
+                Err(error!(
+                    "Migrations are locked by another run since {}. The lock expires after {} minutes",
+                    since,
+                    LOCK_TIMEOUT.as_secs() / 60
+                ))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Releases the lock taken by `owner`. A failure is only logged, the lock
+    /// then expires after [`LOCK_TIMEOUT`].
+    async fn unlock(&self, owner: &str) {
+        let result = async {
+            self.fac
+                .mongo_provider
+                .get_database()?
+                .collection::<Document>(&Collections::MigrationLock.to_string())
+                .update_one(
+                    doc! {"_id": LOCK_ID, "owner": owner},
+                    doc! {"$set": {"owner": null}},
+                    None,
+                )
+                .await?;
+            Ok::<_, anyhow::Error>(())
+        }
+        .await;
+
+        if let Err(e) = result {
+            log::error!("Unable to release the migration lock: {}", e);
+        }
+    }
+
+    async fn applied(&self) -> Result<Vec<AppliedMigration>> {
+        let col = self.collection()?;
+        let mut cursor = col.find(doc! {}, None).await?;
+
+        let mut applied = Vec::new();
+        while let Some(m) = cursor.next().await {
+            applied.push(m?);
+        }
+        applied.sort_by_key(|m| m.version);
+
+        Ok(applied)
+    }
+
+    pub async fn status(&self) -> Result<Vec<MigrationStatus>> {
+        let applied = self.applied().await?;
+
+        let status = self
+            .migrations
+            .iter()
+            .map(|m| MigrationStatus {
+                version: m.version(),
+                name: m.name().to_string(),
+                applied_at: applied
+                    .iter()
+                    .find(|a| a.version as u64 == m.version())
+                    .map(|a| a.applied_at),
+            })
+            .collect();
+
+        Ok(status)
+    }
+
+    /// Fails while any registered migration is not applied. Run at startup so
+    /// the server never serves a schema its indexes and queries don't expect.
+    pub async fn ensure_applied(&self) -> Result<()> {
+        let pending: Vec<String> = self
+            .status()
+            .await?
+            .into_iter()
+            .filter(|m| m.applied_at.is_none())
+            .map(|m| format!("{} {}", m.version, m.name))
+            .collect();
+
+        if !pending.is_empty() {
+            return Err(error!(
+                "Pending migrations: {}. Run `migrate up` before starting the server",
+                pending.join(", ")
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Applies pending migrations in version order, up to and including `target`
+    /// when given. Stops at the first failure.
+    pub async fn up(&self, target: Option<u64>) -> Result<Vec<u64>> {
+        let owner = self.lock().await?;
+        let result = self.apply_up(target).await;
+        self.unlock(&owner).await;
+
+        result
+    }
+
+    async fn apply_up(&self, target: Option<u64>) -> Result<Vec<u64>> {
+        let db = self.fac.mongo_provider.get_database()?;
+        let col = self.collection()?;
+        let applied = self.applied().await?;
+
+        let mut done = Vec::new();
+        for m in &self.migrations {
+            if target.map(|t| m.version() > t).unwrap_or(false) {
+                break;
+            }
+            if applied.iter().any(|a| a.version as u64 == m.version()) {
+                continue;
+            }
+
+            log::info!("Applying migration {} {}", m.version(), m.name());
+            m.up(&db)
+                .await
+                .map_err(|e| error!("Migration {} {} failed: {}", m.version(), m.name(), e))?;
+
+            col.insert_one(
+                AppliedMigration {
+                    version: i64::try_from(m.version())?,
+                    name: m.name().to_string(),
+                    applied_at: chrono::Utc::now(),
+                },
+                None,
+            )
+            .await?;
+            done.push(m.version());
+        }
+
+        Ok(done)
+    }
+
+    /// Reverts the last `steps` applied migrations, newest first.
+    pub async fn down(&self, steps: usize) -> Result<Vec<u64>> {
+        let owner = self.lock().await?;
+        let result = self.apply_down(steps).await;
+        self.unlock(&owner).await;
+
+        result
+    }
+
+    async fn apply_down(&self, steps: usize) -> Result<Vec<u64>> {
+        let db = self.fac.mongo_provider.get_database()?;
+        let col = self.collection()?;
+        let applied = self.applied().await?;
+
+        let mut done = Vec::new();
+        for record in applied.iter().rev().take(steps) {
+            let m = self
+                .migrations
+                .iter()
+                .find(|m| m.version() == record.version as u64)
+                .ok_or(error!(
+                    "Applied migration {} {} is not registered",
+                    record.version, record.name
+                ))?;
+
+            log::info!("Reverting migration {} {}", m.version(), m.name());
+            m.down(&db).await.map_err(|e| {
+                error!(
+                    "Migration {} {} revert failed: {}",
+                    m.version(),
+                    m.name(),
+                    e
+                )
+            })?;
+
+            col.delete_one(doc! {"_id": record.version}, None).await?;
+            done.push(m.version());
+        }
+
+        Ok(done)
+    }
+
+    /// Handles `migrate up [version]`, `migrate down [steps]` and `migrate status`.
+    /// `args` are the arguments after `migrate`.
+    pub async fn run_command(&self, args: &[String]) -> Result<()> {
+        match args.first().map(|a| a.as_str()) {
+            Some("up") => {
+                let target = args.get(1).map(|v| v.parse()).transpose()?;
+                let done = self.up(target).await?;
+                log::info!("Applied {} migration(s): {:?}", done.len(), done);
+            }
+            Some("down") => {
+                let steps = args.get(1).map(|v| v.parse()).transpose()?.unwrap_or(1);
+                let done = self.down(steps).await?;
+                log::info!("Reverted {} migration(s): {:?}", done.len(), done);
+            }
+            Some("status") | None => {
+                for m in self.status().await? {
+                    let state = match m.applied_at {
+                        Some(at) => format!("applied {}", at.to_rfc3339()),
+                        None => "pending".to_string(),
+                    };
+                    log::info!("{:>12}  {:<40} {}", m.version, m.name, state);
+                }
+            }
+            Some(other) => {
+                return Err(error!(
+                    "Unknown migrate command `{}`. Use up [version], down [steps] or status",
+                    other
+                ))
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn other_storage_backends_are_refused() {
+        let mut fac = ApplicationFactory::in_memory();
+        fac.config = Arc::new(crate::config::AppConfig {
+            storage_backend: StorageBackend::Sql,
+            ..Default::default()
+        });
+
+        let err = match MigrationRunner::new(Arc::new(fac), crate::app::migrations::migrations()) {
+            Ok(_) => panic!("runner created for the sql backend"),
+            Err(e) => e,
+        };
+        assert!(err.to_string().contains("`sql` is configured"), "{}", err);
+    }
+}
@@ -0,0 +1,14 @@
+use crate::app::migration::Migration;
+
+pub mod user_default_roles;
+pub mod user_drop_name_index;
+pub mod user_live_marker;
+
+/// Every migration of the application. Register new migrations here.
+pub fn migrations() -> Vec<Box<dyn Migration>> {
+    vec![
+        Box::new(user_default_roles::UserDefaultRoles),
+        Box::new(user_live_marker::UserLiveMarker),
+        Box::new(user_drop_name_index::UserDropNameIndex),
+    ]
+}
@@ -0,0 +1,38 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use mongodb::bson::{doc, Document};
+
+use crate::app::collections::Collections;
+use crate::app::migration::Migration;
+use crate::auth::permissions::Role;
+
+/// Gives users created before roles existed the `user` role.
+pub struct UserDefaultRoles;
+
+#[async_trait]
+impl Migration for UserDefaultRoles {
+    fn version(&self) -> u64 {
+        2023101801
+    }
+
+    fn name(&self) -> &str {
+        "user_default_roles"
+    }
+
+    async fn up(&self, db: &mongodb::Database) -> Result<()> {
+        let col = db.collection::<Document>(&Collections::User.to_string());
+        col.update_many(
+            doc! {"roles": {"$exists": false}},
+            doc! {"$set": {"roles": [Role::User.to_string()], "permissions": []}},
+            None,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _db: &mongodb::Database) -> Result<()> {
+        // Roles may have been changed since, so they are left in place.
+        Ok(())
+    }
+}
@@ -0,0 +1,44 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use mongodb::bson::{doc, Document};
+use mongodb::error::ErrorKind;
+use mongodb::IndexModel;
+
+use crate::app::collections::Collections;
+use crate::app::migration::Migration;
+
+/// Drops the `name_1` index created by earlier versions. Users have no `name`.
+pub struct UserDropNameIndex;
+
+const INDEX_NAME: &str = "name_1";
+
+#[async_trait]
+impl Migration for UserDropNameIndex {
+    fn version(&self) -> u64 {
+        2023102301
+    }
+
+    fn name(&self) -> &str {
+        "user_drop_name_index"
+    }
+
+    async fn up(&self, db: &mongodb::Database) -> Result<()> {
+        let col = db.collection::<Document>(&Collections::User.to_string());
+        match col.drop_index(INDEX_NAME, None).await {
+            Ok(()) => Ok(()),
+            // IndexNotFound or NamespaceNotFound, nothing to drop.
+            Err(e) if matches!(&*e.kind, ErrorKind::Command(c) if c.code == 27 || c.code == 26) => {
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn down(&self, db: &mongodb::Database) -> Result<()> {
+        let col = db.collection::<Document>(&Collections::User.to_string());
+        col.create_index(IndexModel::builder().keys(doc! {"name": 1}).build(), None)
+            .await?;
+
+        Ok(())
+    }
+}
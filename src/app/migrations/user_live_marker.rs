@@ -0,0 +1,38 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use mongodb::bson::{doc, Document};
+
+use crate::app::collections::Collections;
+use crate::app::migration::Migration;
+
+/// Stores `deleted_at: null` on users written before it was stored, so the
+/// live only unique index on `email` covers them.
+pub struct UserLiveMarker;
+
+#[async_trait]
+impl Migration for UserLiveMarker {
+    fn version(&self) -> u64 {
+        2023102001
+    }
+
+    fn name(&self) -> &str {
+        "user_live_marker"
+    }
+
+    async fn up(&self, db: &mongodb::Database) -> Result<()> {
+        let col = db.collection::<Document>(&Collections::User.to_string());
+        col.update_many(
+            doc! {"deleted_at": {"$exists": false}},
+            doc! {"$set": {"deleted_at": null}},
+            None,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _db: &mongodb::Database) -> Result<()> {
+        // A stored null reads the same as a missing field.
+        Ok(())
+    }
+}
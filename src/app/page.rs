@@ -0,0 +1,316 @@
+use anyhow::Result;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use mongodb::bson::{doc, Bson, Document};
+use serde::{Deserialize, Serialize};
+
+use crate::app::dao::DaoError;
+use crate::app::dto::DTO;
+use crate::persistence::document_filter::lookup;
+
+pub const DEFAULT_PAGE_SIZE: i64 = 10;
+pub const MAX_PAGE_SIZE: i64 = 100;
+
+/// Paging parameters, usually taken from the query string.
+///
+/// Either `page` (offset paging, starting at 1) or one of the `after`/`before`
+/// cursors from a previous [`Page`] can be given. Cursors hold the sort key
+/// values of a row, `created_at` and `_id` by default, and stay stable while
+/// documents are inserted. A cursor only works with the sort it was made for.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PageRequest {
+    pub page: Option<u64>,
+    pub page_size: Option<i64>,
+    pub after: Option<String>,
+    pub before: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum PageMode {
+    Offset(u64),
+    /// Rows following the cursor's row in sort order.
+    After(Document),
+    /// Rows preceding the cursor's row in sort order.
+    Before(Document),
+}
+
+/// A [`PageRequest`] with defaults applied and limits checked.
+#[derive(Debug, Clone)]
+pub struct ValidPageRequest {
+    pub mode: PageMode,
+    pub page_size: i64,
+}
+
+impl ValidPageRequest {
+    pub fn skip(&self) -> Result<u64> {
+        match self.mode {
+            PageMode::Offset(page) => {
+                (page - 1)
+                    .checked_mul(self.page_size as u64)
+                    .ok_or_else(|| {
+                        DaoError::Invalid(format!("page `{}` is out of range", page)).into()
+                    })
+            }
+            _ => Ok(0),
+        }
+    }
+}
+
+impl PageRequest {
+    pub fn new(page: u64, page_size: i64) -> Self {
+        Self {
+            page: Some(page),
+            page_size: Some(page_size),
+            ..Default::default()
+        }
+    }
+
+    pub fn after(cursor: &str, page_size: i64) -> Self {
+        Self {
+            after: Some(cursor.to_string()),
+            page_size: Some(page_size),
+            ..Default::default()
+        }
+    }
+
+    pub fn validate(&self) -> Result<ValidPageRequest> {
+        let page_size = self.page_size.unwrap_or(DEFAULT_PAGE_SIZE);
+
+        if !(1..=MAX_PAGE_SIZE).contains(&page_size) {
+            return Err(DaoError::Invalid(format!(
+                "page_size must be between 1 and {}",
+                MAX_PAGE_SIZE
+            ))
+            .into());
+        }
+
+        let cursor = |c: &str| {
+            decode_cursor(c).ok_or_else(|| DaoError::Invalid(format!("cursor `{}` is invalid", c)))
+        };
+
+        let mode = match (self.page, &self.after, &self.before) {
+            (None, None, None) => PageMode::Offset(1),
+            (Some(0), None, None) => {
+                return Err(DaoError::Invalid("page must be at least 1".to_string()).into())
+            }
+            (Some(page), None, None) => PageMode::Offset(page),
+            (None, Some(after), None) => PageMode::After(cursor(after)?),
+            (None, None, Some(before)) => PageMode::Before(cursor(before)?),
+            _ => {
+                return Err(DaoError::Invalid(
+                    "only one of `page`, `after` or `before` can be given".to_string(),
+                )
+                .into())
+            }
+        };
+
+        Ok(ValidPageRequest { mode, page_size })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<DTO<T>>,
+    /// Number of documents matching the query across all pages.
+    pub total: u64,
+    /// Set for offset paging only.
+    pub page: Option<u64>,
+    pub page_size: i64,
+    pub next_cursor: Option<String>,
+    pub prev_cursor: Option<String>,
+}
+
+/// Values of the `sort` keys on `row`, what a cursor for it holds.
+pub fn cursor_keys(row: &Document, sort: &Document) -> Document {
+    sort.keys()
+        .map(|field| {
+            let value = lookup(row, field).cloned().unwrap_or(Bson::Null);
+            (field.clone(), value)
+        })
+        .collect()
+}
+
+/// Opaque cursor for `row` under `sort`.
+pub fn encode_cursor(row: &Document, sort: &Document) -> Result<String> {
+    let mut bytes = vec![];
+    cursor_keys(row, sort).to_writer(&mut bytes)?;
+
+    Ok(URL_SAFE_NO_PAD.encode(bytes))
+}
+
+fn decode_cursor(cursor: &str) -> Option<Document> {
+    let bytes = URL_SAFE_NO_PAD.decode(cursor.trim()).ok()?;
+    Document::from_reader(bytes.as_slice()).ok()
+}
+
+fn is_descending(direction: &Bson) -> bool {
+    match direction {
+        Bson::Int32(d) => *d < 0,
+        Bson::Int64(d) => *d < 0,
+        Bson::Double(d) => *d < 0.0,
+        _ => false,
+    }
+}
+
+/// `sort` with every direction flipped.
+pub fn reverse_sort(sort: &Document) -> Document {
+    sort.iter()
+        .map(|(field, direction)| {
+            let flipped = if is_descending(direction) { 1 } else { -1 };
+            (field.clone(), Bson::Int32(flipped))
+        })
+        .collect()
+}
+
+/// Filter for the rows following the `cursor` row in `sort` order, or preceding
+/// it when `forward` is false. Keys compare in order, later keys breaking ties
+/// of the earlier ones.
+pub fn keyset_filter(sort: &Document, cursor: &Document, forward: bool) -> Result<Document> {
+    if !cursor.keys().eq(sort.keys()) {
+        return Err(DaoError::Invalid("cursor does not match the sort order".to_string()).into());
+    }
+
+    let mut branches = vec![];
+    let mut equal = Document::new();
+    for (field, direction) in sort {
+        let value = cursor.get(field).cloned().unwrap_or(Bson::Null);
+        let op = if is_descending(direction) == forward {
+            "$lt"
+        } else {
+            "$gt"
+        };
+
+        let mut branch = equal.clone();
+        branch.insert(field, doc! {op: value.clone()});
+        branches.push(Bson::Document(branch));
+        equal.insert(field, value);
+    }
+
+    Ok(doc! {"$or": branches})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::oid::ObjectId;
+
+    fn sort() -> Document {
+        doc! {"created_at": -1, "_id": -1}
+    }
+
+    fn invalid(result: Result<ValidPageRequest>) -> String {
+        let err = result.unwrap_err();
+        match err.downcast_ref::<DaoError>() {
+            Some(DaoError::Invalid(message)) => message.clone(),
+            _ => panic!("expected DaoError::Invalid, got {:?}", err),
+        }
+    }
+
+    fn after(cursor: &str) -> PageRequest {
+        PageRequest::after(cursor, DEFAULT_PAGE_SIZE)
+    }
+
+    #[test]
+    fn cursors_round_trip_the_sort_keys() {
+        let id = ObjectId::new();
+        let created_at = mongodb::bson::DateTime::now();
+        let row = doc! {"_id": id, "created_at": created_at, "email": "a@example.com"};
+
+        let cursor = encode_cursor(&row, &sort()).unwrap();
+
+        match after(&cursor).validate().unwrap().mode {
+            PageMode::After(keys) => {
+                assert_eq!(keys, doc! {"created_at": created_at, "_id": id});
+            }
+            mode => panic!("expected an after cursor, got {:?}", mode),
+        }
+    }
+
+    #[test]
+    fn missing_sort_keys_are_stored_as_null() {
+        let keys = cursor_keys(&doc! {"_id": 1}, &doc! {"profile.name": 1, "_id": 1});
+
+        assert_eq!(keys, doc! {"profile.name": Bson::Null, "_id": 1});
+    }
+
+    #[test]
+    fn invalid_cursors_are_rejected() {
+        let cursor = encode_cursor(&doc! {"_id": 1, "created_at": 2}, &sort()).unwrap();
+        let truncated = &cursor[..cursor.len() - 4];
+
+        for cursor in ["not base64!", "", "AAAA", truncated] {
+            let message = invalid(after(cursor).validate());
+            assert!(message.contains("is invalid"), "{}", message);
+        }
+    }
+
+    #[test]
+    fn tampered_cursors_do_not_match_the_sort() {
+        let keys = decode_cursor(&encode_cursor(&doc! {"_id": 1}, &doc! {"_id": 1}).unwrap());
+        let err = keyset_filter(&sort(), &keys.unwrap(), true).unwrap_err();
+        assert!(err.to_string().contains("does not match"), "{}", err);
+
+        // Same keys in another order are another sort.
+        let reordered = doc! {"_id": 1, "created_at": 2};
+        assert!(keyset_filter(&sort(), &reordered, true).is_err());
+    }
+
+    #[test]
+    fn keyset_filter_breaks_ties_on_later_keys() {
+        let cursor = doc! {"created_at": 2, "_id": 1};
+
+        assert_eq!(
+            keyset_filter(&sort(), &cursor, true).unwrap(),
+            doc! {"$or": [
+                {"created_at": {"$lt": 2}},
+                {"created_at": 2, "_id": {"$lt": 1}},
+            ]}
+        );
+        assert_eq!(
+            keyset_filter(&sort(), &cursor, false).unwrap(),
+            doc! {"$or": [
+                {"created_at": {"$gt": 2}},
+                {"created_at": 2, "_id": {"$gt": 1}},
+            ]}
+        );
+        assert_eq!(reverse_sort(&sort()), doc! {"created_at": 1, "_id": 1});
+    }
+
+    #[test]
+    fn page_size_is_limited() {
+        let default = PageRequest::default().validate().unwrap();
+        assert_eq!(default.page_size, DEFAULT_PAGE_SIZE);
+        assert!(matches!(default.mode, PageMode::Offset(1)));
+
+        assert_eq!(
+            PageRequest::new(1, MAX_PAGE_SIZE)
+                .validate()
+                .unwrap()
+                .page_size,
+            MAX_PAGE_SIZE
+        );
+        for page_size in [0, -1, MAX_PAGE_SIZE + 1] {
+            let message = invalid(PageRequest::new(1, page_size).validate());
+            assert!(message.contains("page_size"), "{}", message);
+        }
+    }
+
+    #[test]
+    fn offsets_are_checked() {
+        assert!(invalid(PageRequest::new(0, 10).validate()).contains("at least 1"));
+        assert_eq!(
+            PageRequest::new(3, 10).validate().unwrap().skip().unwrap(),
+            20
+        );
+        assert!(PageRequest::new(u64::MAX, 10)
+            .validate()
+            .unwrap()
+            .skip()
+            .is_err());
+
+        let both = PageRequest {
+            after: Some("x".to_string()),
+            ..PageRequest::new(1, 10)
+        };
+        assert!(invalid(both.validate()).contains("only one of"));
+    }
+}
@@ -0,0 +1,567 @@
+use std::str::FromStr;
+
+use anyhow::Result;
+
+use crate::app::dao::DaoError;
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use mongodb::options::FindOptions;
+
+/// Typed query filter converted to a Mongo filter document.
+///
+/// ```ignore
+/// let filter = Filter::eq("email", "a@b.com").or(Filter::exists("deleted_at", false));
+/// ```
+// Filters are built once per request, boxing `Range` would only add noise.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Filter {
+    #[default]
+    All,
+    Eq(String, Bson),
+    Ne(String, Bson),
+    In(String, Vec<Bson>),
+    Range {
+        field: String,
+        gt: Option<Bson>,
+        gte: Option<Bson>,
+        lt: Option<Bson>,
+        lte: Option<Bson>,
+    },
+    Regex {
+        field: String,
+        pattern: String,
+        options: String,
+    },
+    Exists(String, bool),
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+}
+
+impl Filter {
+    pub fn eq(field: &str, value: impl Into<Bson>) -> Self {
+        Filter::Eq(field.to_string(), value.into())
+    }
+
+    pub fn ne(field: &str, value: impl Into<Bson>) -> Self {
+        Filter::Ne(field.to_string(), value.into())
+    }
+
+    pub fn is_in<V: Into<Bson>>(field: &str, values: impl IntoIterator<Item = V>) -> Self {
+        Filter::In(
+            field.to_string(),
+            values.into_iter().map(Into::into).collect(),
+        )
+    }
+
+    pub fn range(field: &str) -> Self {
+        Filter::Range {
+            field: field.to_string(),
+            gt: None,
+            gte: None,
+            lt: None,
+            lte: None,
+        }
+    }
+
+    pub fn gt(field: &str, value: impl Into<Bson>) -> Self {
+        Filter::range(field).with_bound("gt", value.into())
+    }
+
+    pub fn gte(field: &str, value: impl Into<Bson>) -> Self {
+        Filter::range(field).with_bound("gte", value.into())
+    }
+
+    pub fn lt(field: &str, value: impl Into<Bson>) -> Self {
+        Filter::range(field).with_bound("lt", value.into())
+    }
+
+    pub fn lte(field: &str, value: impl Into<Bson>) -> Self {
+        Filter::range(field).with_bound("lte", value.into())
+    }
+
+    /// Sets one bound (`gt`, `gte`, `lt` or `lte`) on a [`Filter::Range`].
+    /// Other filters are returned unchanged.
+    pub fn with_bound(mut self, bound: &str, value: Bson) -> Self {
+        if let Filter::Range {
+            gt, gte, lt, lte, ..
+        } = &mut self
+        {
+            match bound {
+                "gt" => *gt = Some(value),
+                "gte" => *gte = Some(value),
+                "lt" => *lt = Some(value),
+                "lte" => *lte = Some(value),
+                _ => {}
+            }
+        }
+        self
+    }
+
+    /// Raw regular expression. Use [`Filter::contains`] for user supplied text.
+    pub fn regex(field: &str, pattern: &str, options: &str) -> Self {
+        Filter::Regex {
+            field: field.to_string(),
+            pattern: pattern.to_string(),
+            options: options.to_string(),
+        }
+    }
+
+    /// Case insensitive substring match. `text` is escaped.
+    pub fn contains(field: &str, text: &str) -> Self {
+        Filter::regex(field, &escape_regex(text), "i")
+    }
+
+    /// Case insensitive prefix match. `text` is escaped.
+    pub fn starts_with(field: &str, text: &str) -> Self {
+        Filter::regex(field, &format!("^{}", escape_regex(text)), "i")
+    }
+
+    pub fn exists(field: &str, exists: bool) -> Self {
+        Filter::Exists(field.to_string(), exists)
+    }
+
+    pub fn and(self, other: Filter) -> Self {
+        match (self, other) {
+            (Filter::All, f) | (f, Filter::All) => f,
+            (Filter::And(mut a), Filter::And(b)) => {
+                a.extend(b);
+                Filter::And(a)
+            }
+            (Filter::And(mut a), f) => {
+                a.push(f);
+                Filter::And(a)
+            }
+            (f, other) => Filter::And(vec![f, other]),
+        }
+    }
+
+    pub fn or(self, other: Filter) -> Self {
+        match (self, other) {
+            (Filter::Or(mut a), Filter::Or(b)) => {
+                a.extend(b);
+                Filter::Or(a)
+            }
+            (Filter::Or(mut a), f) => {
+                a.push(f);
+                Filter::Or(a)
+            }
+            (f, other) => Filter::Or(vec![f, other]),
+        }
+    }
+
+    pub fn to_document(&self) -> Document {
+        match self {
+            Filter::All => doc! {},
+            Filter::Eq(f, v) => doc! { f: { "$eq": v.clone() } },
+            Filter::Ne(f, v) => doc! { f: { "$ne": v.clone() } },
+            Filter::In(f, v) => doc! { f: { "$in": v.clone() } },
+            Filter::Range {
+                field,
+                gt,
+                gte,
+                lt,
+                lte,
+            } => {
+                let mut range = Document::new();
+                for (op, v) in [("$gt", gt), ("$gte", gte), ("$lt", lt), ("$lte", lte)] {
+                    if let Some(v) = v {
+                        range.insert(op, v.clone());
+                    }
+                }
+                doc! { field: range }
+            }
+            Filter::Regex {
+                field,
+                pattern,
+                options,
+            } => doc! { field: { "$regex": pattern, "$options": options } },
+            Filter::Exists(f, v) => doc! { f: { "$exists": *v } },
+            Filter::And(v) if v.is_empty() => doc! {},
+            Filter::And(v) => {
+                doc! { "$and": v.iter().map(|e| e.to_document()).collect::<Vec<_>>() }
+            }
+            Filter::Or(v) if v.is_empty() => doc! {},
+            Filter::Or(v) => doc! { "$or": v.iter().map(|e| e.to_document()).collect::<Vec<_>>() },
+        }
+    }
+}
+
+impl From<Filter> for Document {
+    fn from(value: Filter) -> Self {
+        value.to_document()
+    }
+}
+
+fn escape_regex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\^$.|?*+()[]{}/-".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sort {
+    pub fields: Vec<(String, SortDirection)>,
+}
+
+impl Sort {
+    pub fn asc(field: &str) -> Self {
+        Self::default().then_asc(field)
+    }
+
+    pub fn desc(field: &str) -> Self {
+        Self::default().then_desc(field)
+    }
+
+    pub fn then_asc(mut self, field: &str) -> Self {
+        self.fields.push((field.to_string(), SortDirection::Asc));
+        self
+    }
+
+    pub fn then_desc(mut self, field: &str) -> Self {
+        self.fields.push((field.to_string(), SortDirection::Desc));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn to_document(&self) -> Document {
+        let mut sort = Document::new();
+        for (field, direction) in &self.fields {
+            let direction = match direction {
+                SortDirection::Asc => 1,
+                SortDirection::Desc => -1,
+            };
+            sort.insert(field, direction);
+        }
+        sort
+    }
+}
+
+/// Filter and sort passed to `DaoObj::query`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FindQuery {
+    pub filter: Filter,
+    pub sort: Option<Sort>,
+}
+
+impl FindQuery {
+    pub fn new(filter: Filter) -> Self {
+        Self { filter, sort: None }
+    }
+
+    pub fn sort(mut self, sort: Sort) -> Self {
+        self.sort = Some(sort);
+        self
+    }
+
+    pub fn find_options(&self) -> Option<FindOptions> {
+        let sort = self.sort.as_ref().filter(|s| !s.is_empty())?;
+
+        let mut opt = FindOptions::default();
+        opt.sort = Some(sort.to_document());
+        Some(opt)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldType {
+    String,
+    Int,
+    Float,
+    Bool,
+    ObjectId,
+}
+
+impl FieldType {
+    fn parse(&self, value: &str) -> Result<Bson> {
+        let parsed = match self {
+            FieldType::String => Bson::String(value.to_string()),
+            FieldType::Int => Bson::Int64(value.parse()?),
+            FieldType::Float => Bson::Double(value.parse()?),
+            FieldType::Bool => Bson::Boolean(value.parse()?),
+            FieldType::ObjectId => Bson::ObjectId(ObjectId::from_str(value)?),
+        };
+        Ok(parsed)
+    }
+}
+
+/// Fields a list endpoint lets clients filter and sort on.
+///
+/// Parses `?filter[email]=a@b.com&filter[age][gte]=18&sort=-created_at,email`.
+/// Supported operators are `eq` (default), `ne`, `in` (comma separated), `gt`,
+/// `gte`, `lt`, `lte`, `contains`, `starts_with` and `exists`. Only declared
+/// fields are accepted and values are always treated as data, so clients cannot
+/// inject Mongo operators.
+#[derive(Debug, Clone, Default)]
+pub struct QuerySpec {
+    filterable: Vec<(String, FieldType)>,
+    sortable: Vec<String>,
+}
+
+impl QuerySpec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn filter(mut self, field: &str, field_type: FieldType) -> Self {
+        self.filterable.push((field.to_string(), field_type));
+        self
+    }
+
+    pub fn sort(mut self, field: &str) -> Self {
+        self.sortable.push(field.to_string());
+        self
+    }
+
+    fn field_type(&self, field: &str) -> Result<FieldType> {
+        self.filterable
+            .iter()
+            .find(|(f, _)| f == field)
+            .map(|(_, t)| *t)
+            .ok_or_else(|| {
+                DaoError::Invalid(format!("Filtering on `{}` is not allowed", field)).into()
+            })
+    }
+
+    fn parse_filter(&self, key: &str, value: &str) -> Result<Filter> {
+        // key is `filter[field]` or `filter[field][op]`
+        let inner = key
+            .strip_prefix("filter[")
+            .and_then(|k| k.strip_suffix(']'))
+            .ok_or(DaoError::Invalid(format!("Invalid filter `{}`", key)))?;
+
+        let (field, op) = match inner.split_once("][") {
+            Some((field, op)) => (field, op),
+            None => (inner, "eq"),
+        };
+
+        let field_type = self.field_type(field)?;
+        let parse = |v: &str| {
+            field_type.parse(v).map_err(|e| {
+                DaoError::Invalid(format!("Invalid value `{}` for `{}`: {}", v, field, e))
+            })
+        };
+
+        let filter = match op {
+            "eq" => Filter::Eq(field.to_string(), parse(value)?),
+            "ne" => Filter::Ne(field.to_string(), parse(value)?),
+            "in" => Filter::In(
+                field.to_string(),
+                value.split(',').map(parse).collect::<Result<Vec<_>, _>>()?,
+            ),
+            "gt" | "gte" | "lt" | "lte" => Filter::range(field).with_bound(op, parse(value)?),
+            "contains" | "starts_with" if field_type == FieldType::String => {
+                if op == "contains" {
+                    Filter::contains(field, value)
+                } else {
+                    Filter::starts_with(field, value)
+                }
+            }
+            "exists" => Filter::exists(
+                field,
+                value.parse().map_err(|_| {
+                    DaoError::Invalid(format!("exists on `{}` expects true or false", field))
+                })?,
+            ),
+            _ => {
+                return Err(DaoError::Invalid(format!(
+                    "Unsupported filter operator `{}` on `{}`",
+                    op, field
+                ))
+                .into())
+            }
+        };
+
+        Ok(filter)
+    }
+
+    fn parse_sort(&self, value: &str) -> Result<Sort> {
+        let mut sort = Sort::default();
+
+        for field in value.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+            let (field, desc) = match field.strip_prefix('-') {
+                Some(f) => (f, true),
+                None => (field.strip_prefix('+').unwrap_or(field), false),
+            };
+
+            if !self.sortable.iter().any(|f| f == field) {
+                return Err(
+                    DaoError::Invalid(format!("Sorting on `{}` is not allowed", field)).into(),
+                );
+            }
+
+            sort = if desc {
+                sort.then_desc(field)
+            } else {
+                sort.then_asc(field)
+            };
+        }
+
+        Ok(sort)
+    }
+
+    /// Builds a [`FindQuery`] from query string pairs. Keys other than
+    /// `filter[...]` and `sort` (paging parameters for example) are ignored.
+    pub fn parse(&self, params: &[(String, String)]) -> Result<FindQuery> {
+        let mut query = FindQuery::default();
+
+        for (key, value) in params {
+            if key == "sort" {
+                query.sort = Some(self.parse_sort(value)?);
+            } else if key.starts_with("filter[") {
+                query.filter = query.filter.and(self.parse_filter(key, value)?);
+            }
+        }
+
+        Ok(query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec() -> QuerySpec {
+        QuerySpec::new()
+            .filter("email", FieldType::String)
+            .filter("age", FieldType::Int)
+            .filter("score", FieldType::Float)
+            .filter("active", FieldType::Bool)
+            .filter("owner", FieldType::ObjectId)
+            .sort("email")
+            .sort("created_at")
+    }
+
+    fn parse(pairs: &[(&str, &str)]) -> Result<FindQuery> {
+        let params: Vec<(String, String)> = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        spec().parse(&params)
+    }
+
+    fn rejected(pairs: &[(&str, &str)]) -> String {
+        let err = parse(pairs).unwrap_err();
+        match err.downcast_ref::<DaoError>() {
+            Some(DaoError::Invalid(message)) => message.clone(),
+            _ => panic!("expected DaoError::Invalid, got {:?}", err),
+        }
+    }
+
+    #[test]
+    fn mongo_operators_are_rejected() {
+        for op in ["$where", "$regex", "$expr", "$gt", "unknown"] {
+            let key = format!("filter[email][{}]", op);
+            let message = rejected(&[(key.as_str(), "1")]);
+            assert!(
+                message.contains("Unsupported filter operator"),
+                "{}",
+                message
+            );
+        }
+
+        let message = rejected(&[("filter[$where]", "sleep(1000)")]);
+        assert!(message.contains("not allowed"), "{}", message);
+    }
+
+    #[test]
+    fn undeclared_fields_are_refused() {
+        let message = rejected(&[("filter[password]", "secret")]);
+        assert!(message.contains("`password`"), "{}", message);
+
+        let message = rejected(&[("sort", "-password")]);
+        assert!(message.contains("Sorting on `password`"), "{}", message);
+
+        let message = rejected(&[("filter[email", "a")]);
+        assert!(message.contains("Invalid filter"), "{}", message);
+    }
+
+    #[test]
+    fn values_are_typed_by_field() {
+        let query = parse(&[
+            ("filter[email]", "a@example.com"),
+            ("filter[age][gte]", "18"),
+            ("filter[score][lt]", "2.5"),
+            ("filter[active]", "true"),
+            ("filter[owner]", "64b7f1f2a1b2c3d4e5f60718"),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            query.filter,
+            Filter::And(vec![
+                Filter::eq("email", "a@example.com"),
+                Filter::gte("age", 18i64),
+                Filter::lt("score", 2.5),
+                Filter::eq("active", true),
+                Filter::eq(
+                    "owner",
+                    ObjectId::from_str("64b7f1f2a1b2c3d4e5f60718").unwrap()
+                ),
+            ])
+        );
+
+        for (key, value) in [
+            ("filter[age]", "eighteen"),
+            ("filter[score]", "high"),
+            ("filter[active]", "yes"),
+            ("filter[owner]", "not-an-id"),
+            ("filter[age][in]", "1,two"),
+        ] {
+            let message = rejected(&[(key, value)]);
+            assert!(message.contains("Invalid value"), "{}", message);
+        }
+    }
+
+    #[test]
+    fn values_are_data_not_operators() {
+        let query = parse(&[("filter[email]", "{\"$ne\": null}")]).unwrap();
+        assert_eq!(
+            query.filter.to_document(),
+            doc! {"email": {"$eq": "{\"$ne\": null}"}}
+        );
+
+        let query = parse(&[("filter[email][contains]", ".*")]).unwrap();
+        assert_eq!(
+            query.filter.to_document(),
+            doc! {"email": {"$regex": "\\.\\*", "$options": "i"}}
+        );
+
+        // Text operators only apply to string fields.
+        assert!(rejected(&[("filter[age][contains]", "1")]).contains("Unsupported"));
+    }
+
+    #[test]
+    fn parses_lists_existence_and_sort() {
+        let query = parse(&[
+            ("filter[age][in]", "1,2"),
+            ("filter[email][exists]", "false"),
+            ("sort", "-created_at,email"),
+            ("page", "2"),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            query.filter,
+            Filter::And(vec![
+                Filter::is_in("age", [1i64, 2]),
+                Filter::exists("email", false),
+            ])
+        );
+        assert_eq!(query.sort, Some(Sort::desc("created_at").then_asc("email")));
+        assert!(rejected(&[("filter[email][exists]", "maybe")]).contains("true or false"));
+    }
+}
@@ -3,6 +3,8 @@ use crate::app::dao::DaoObj;
 use std::sync::Arc;
 
 use crate::app::dto::DTO;
+use crate::app::page::{Page, PageRequest};
+use crate::app::query::{FindQuery, QuerySpec};
 use anyhow::Result;
 use serde::{de::DeserializeOwned, Serialize};
 
@@ -15,6 +17,11 @@ where
 {
     fn get_dao(&self) -> Arc<dyn DaoObj<T>>;
 
+    /// Fields clients may filter and sort on from list endpoints.
+    fn query_spec(&self) -> QuerySpec {
+        QuerySpec::new().sort("created_at").sort("updated_at")
+    }
+
     async fn create(&self, data: T) -> Result<DTO<T>> {
         let data = DTO::new(data);
         let dao = self.get_dao();
@@ -30,9 +37,16 @@ where
         Ok(result)
     }
 
-    async fn list(&self, page: u64, page_size: i64) -> Result<Vec<DTO<T>>> {
+    async fn list(&self, page: &PageRequest) -> Result<Page<T>> {
+        let dao = self.get_dao();
+        let result = dao.list(page).await?;
+
+        Ok(result)
+    }
+
+    async fn find(&self, query: &FindQuery, page: &PageRequest) -> Result<Page<T>> {
         let dao = self.get_dao();
-        let result = dao.list(page, page_size).await?;
+        let result = dao.query(query, page).await?;
 
         Ok(result)
     }
@@ -44,9 +58,43 @@ where
         Ok(())
     }
 
+    async fn list_trashed(&self, page: &PageRequest) -> Result<Page<T>> {
+        let dao = self.get_dao();
+        let result = dao.list_trashed(page).await?;
+
+        Ok(result)
+    }
+
+    async fn restore(&self, id: &str) -> Result<DTO<T>> {
+        let dao = self.get_dao();
+        let result = dao.restore(id).await?;
+
+        Ok(result)
+    }
+
+    async fn purge(&self, id: &str) -> Result<()> {
+        let dao = self.get_dao();
+        dao.purge(id).await?;
+
+        Ok(())
+    }
+
     async fn update(&self, data: DTO<T>) -> Result<DTO<T>> {
         let dao = self.get_dao();
         let result = dao.update(data).await?;
         Ok(result)
     }
+
+    /// Partial update from a JSON object. See [`DaoObj::patch`].
+    async fn patch(
+        &self,
+        id: &str,
+        changes: serde_json::Value,
+        expected_version: Option<i64>,
+    ) -> Result<DTO<T>> {
+        let changes = mongodb::bson::to_document(&changes)?;
+        let dao = self.get_dao();
+        let result = dao.patch(id, changes, expected_version).await?;
+        Ok(result)
+    }
 }
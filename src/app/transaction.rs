@@ -0,0 +1,81 @@
+use anyhow::Result;
+use mongodb::bson::Document;
+use mongodb::options::FindOptions;
+use mongodb::ClientSession;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::app::dao::DaoObj;
+use crate::app::dto::DTO;
+
+/// Number of times a transaction is attempted when Mongo reports a transient error.
+pub const MAX_TRANSACTION_ATTEMPTS: usize = 5;
+
+/// Handle given to `ApplicationDao::transaction`. Every operation runs inside
+/// the open transaction and only becomes visible once it commits.
+pub struct Transaction {
+    session: ClientSession,
+}
+
+impl Transaction {
+    pub fn new(session: ClientSession) -> Self {
+        Self { session }
+    }
+
+    pub fn session(&mut self) -> &mut ClientSession {
+        &mut self.session
+    }
+
+    pub async fn create<T, D>(&mut self, dao: &D, data: DTO<T>) -> Result<DTO<T>>
+    where
+        T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static,
+        D: DaoObj<T> + ?Sized,
+    {
+        dao.create_with_session(&mut self.session, data).await
+    }
+
+    pub async fn get<T, D>(&mut self, dao: &D, id: &str) -> Result<DTO<T>>
+    where
+        T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static,
+        D: DaoObj<T> + ?Sized,
+    {
+        dao.get_with_session(&mut self.session, id).await
+    }
+
+    pub async fn update<T, D>(&mut self, dao: &D, data: DTO<T>) -> Result<DTO<T>>
+    where
+        T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static,
+        D: DaoObj<T> + ?Sized,
+    {
+        dao.update_with_session(&mut self.session, data).await
+    }
+
+    pub async fn delete<T, D>(&mut self, dao: &D, id: &str) -> Result<()>
+    where
+        T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static,
+        D: DaoObj<T> + ?Sized,
+    {
+        dao.delete_with_session(&mut self.session, id).await
+    }
+
+    pub async fn find<T, D>(
+        &mut self,
+        dao: &D,
+        query: Document,
+        options: Option<FindOptions>,
+    ) -> Result<Vec<DTO<T>>>
+    where
+        T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static,
+        D: DaoObj<T> + ?Sized,
+    {
+        dao.find_with_session(&mut self.session, query, options)
+            .await
+    }
+}
+
+/// Whether the error carries one of the given Mongo error labels.
+pub fn has_error_label(error: &anyhow::Error, label: &str) -> bool {
+    error
+        .chain()
+        .filter_map(|e| e.downcast_ref::<mongodb::error::Error>())
+        .any(|e| e.contains_label(label))
+}
@@ -1,8 +1,10 @@
 pub mod user_dao;
 pub mod user_model;
+pub mod user_view;
 
 pub use user_dao::*;
 pub use user_model::*;
+pub use user_view::*;
 
 pub mod user_service;
 
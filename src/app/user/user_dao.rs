@@ -1,17 +1,18 @@
 use crate::app::dto::DTO;
 use crate::app::user::user_model::User;
+use crate::auth;
+use crate::server_errors::ServerError;
 
-use anyhow::{anyhow as error, Result};
+use anyhow::Result;
 use mongodb::bson::doc;
 
-use std::collections;
 use std::sync::Arc;
 
 use crate::app::collections::Collections;
 use crate::application_factory::ApplicationFactory;
 
-use crate::app::dao::DaoObj;
-use mongodb::{options::IndexOptions, IndexModel};
+use crate::app::dao::{DaoError, DaoObj};
+use crate::app::index::IndexSpec;
 
 use async_trait::async_trait;
 
@@ -30,20 +31,20 @@ impl DaoObj<User> for UserDao {
         &self.collection_name
     }
 
-    async fn init(&self) -> Result<()> {
-        let col = self.get_collection()?;
-        let index = IndexModel::builder()
-            .keys(doc! {"email": 1})
-            .options(IndexOptions::builder().unique(true).build())
-            .build();
-
-        col.create_index(index, None).await?;
-
-        let index = IndexModel::builder().keys(doc! {"name": 1}).build();
+    fn soft_delete(&self) -> bool {
+        true
+    }
 
-        col.create_index(index, None).await?;
+    fn patchable_fields(&self) -> Option<Vec<&'static str>> {
+        // password goes through `User::set_password` so it is always hashed
+        Some(vec!["email"])
+    }
 
-        Ok(())
+    fn indexes(&self) -> Vec<IndexSpec> {
+        vec![
+            // Trashed users don't keep their email from being signed up again.
+            IndexSpec::new().asc("email").unique().live(),
+        ]
     }
 }
 
@@ -56,7 +57,7 @@ impl UserDao {
     }
 
     pub async fn create_user(&mut self, email: &str, password: &str) -> Result<DTO<User>> {
-        let user = User::new(email, password)?;
+        let user = User::new(email, password).await?;
 
         let data = DTO::new(user);
         let result = self.create(data).await?;
@@ -64,25 +65,210 @@ impl UserDao {
     }
 
     pub async fn find_by_email(&self, email: &str) -> Result<DTO<User>> {
-        let col = self.get_collection()?;
-        let user = col
-            .find_one(doc! {"email": email}, None)
+        let user = self
+            .get_store()
+            .find_one(
+                self.get_collection_name(),
+                self.live_filter(doc! {"email": email}),
+            )
             .await?
-            .ok_or(error!("User with email `{}` not found", email))?;
+            .ok_or(DaoError::NotFound {
+                id: email.to_string(),
+            })?;
+
+        self.decode(user)
+    }
+
+    /// The user with `email` if `password` is theirs. An unknown email or a
+    /// wrong password fails with [`ServerError::Unauthorized`], store errors
+    /// are passed on.
+    pub async fn login(&self, email: &str, password: &str) -> Result<DTO<User>> {
+        let invalid = || {
+            anyhow::Error::from(ServerError::Unauthorized(
+                "Invalid email or password".into(),
+            ))
+        };
+
+        let mut user = match self.find_by_email(email).await {
+            Ok(user) => user,
+            Err(e) if matches!(e.downcast_ref(), Some(DaoError::NotFound { .. })) => {
+                // Don't let the response time tell unknown emails apart.
+                auth::verify_dummy_password(password).await;
+                return Err(invalid());
+            }
+            Err(e) => return Err(e),
+        };
+
+        match user.verify_password(password).await {
+            Some(true) => Ok(user),
+            Some(false) => Err(invalid()),
+            None => {
+                // Legacy record holding the plaintext password. Migrate it to an
+                // Argon2 hash on the first successful login. The dummy verify
+                // keeps these as slow as hashed records.
+                auth::verify_dummy_password(password).await;
+                if !user.matches_plaintext_password(password) {
+                    return Err(invalid());
+                }
+
+                user.set_password(password).await?;
+                let user = self.update(user).await?;
+
+                log::info!(
+                    "Migrated plaintext password to argon2 hash for user `{}`",
+                    email
+                );
+
+                Ok(user)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::page::PageRequest;
+
+    async fn user_dao() -> UserDao {
+        let dao = UserDao::new(Arc::new(ApplicationFactory::in_memory())).unwrap();
+        dao.init().await.unwrap();
+        dao
+    }
+
+    /// A user stored before `version` and `deleted_at` were written.
+    async fn insert_legacy(dao: &UserDao, email: &str) -> String {
+        let id = dao
+            .get_store()
+            .insert_one(
+                dao.get_collection_name(),
+                doc! {"email": email, "password": "plaintext"},
+            )
+            .await
+            .unwrap();
 
-        Ok(user)
+        crate::app::dto::id_from_bson(&id).unwrap()
     }
 
-    pub async fn login(&self, email: &str, passwords: &str) -> Result<DTO<User>> {
-        let query = doc! {"email": email, "password": passwords};
-        let result = self.find(query, 1, 2, None).await?;
+    fn emails(page: crate::app::page::Page<User>) -> Vec<String> {
+        let mut emails: Vec<String> = page.items.iter().map(|u| u.email().to_string()).collect();
+        emails.sort();
+        emails
+    }
+
+    #[tokio::test]
+    async fn ne_null_does_not_match_missing_fields() {
+        let mut dao = user_dao().await;
+        dao.create_user("live@example.com", "secret").await.unwrap();
+        let trashed = dao
+            .create_user("trashed@example.com", "secret")
+            .await
+            .unwrap();
+        insert_legacy(&dao, "legacy@example.com").await;
+        dao.delete(trashed.id.as_deref().unwrap()).await.unwrap();
+
+        let page = PageRequest::new(1, 10);
+        assert_eq!(
+            emails(dao.list_trashed(&page).await.unwrap()),
+            ["trashed@example.com"]
+        );
+        assert_eq!(
+            emails(dao.list(&page).await.unwrap()),
+            ["legacy@example.com", "live@example.com"]
+        );
+    }
+
+    #[tokio::test]
+    async fn duplicate_email_is_rejected_among_live_users() {
+        let mut dao = user_dao().await;
+        let first = dao.create_user("a@example.com", "secret").await.unwrap();
+
+        let err = dao.create_user("a@example.com", "other").await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<DaoError>(),
+            Some(DaoError::DuplicateKey(_))
+        ));
+
+        // A trashed user does not hold on to the email.
+        dao.delete(first.id.as_deref().unwrap()).await.unwrap();
+        dao.create_user("a@example.com", "other").await.unwrap();
+
+        let err = dao.restore(first.id.as_deref().unwrap()).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<DaoError>(),
+            Some(DaoError::DuplicateKey(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn deleting_a_missing_user_is_not_found() {
+        let mut dao = user_dao().await;
+        let user = dao.create_user("a@example.com", "secret").await.unwrap();
+        let id = user.id.as_deref().unwrap();
 
-        if result.len() == 0 {
-            return Err(error!("User not found with email ({}) and password", email));
+        dao.delete(id).await.unwrap();
+        for result in [dao.delete(id).await, dao.delete("missing").await] {
+            assert!(matches!(
+                result.unwrap_err().downcast_ref::<DaoError>(),
+                Some(DaoError::NotFound { .. })
+            ));
         }
 
-        let res = result[0].clone();
+        dao.purge(id).await.unwrap();
+        assert!(matches!(
+            dao.purge(id).await.unwrap_err().downcast_ref::<DaoError>(),
+            Some(DaoError::NotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn updates_increment_the_version() {
+        let mut dao = user_dao().await;
+        let user = dao.create_user("a@example.com", "secret").await.unwrap();
+        assert_eq!(user.version, Some(0));
+
+        let updated = dao.update(user.clone()).await.unwrap();
+        assert_eq!(updated.version, Some(1));
+
+        let err = dao.update(user).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<DaoError>(),
+            Some(DaoError::Conflict { expected: 0, .. })
+        ));
+
+        // `$inc` starts a missing version from zero.
+        let id = insert_legacy(&dao, "legacy@example.com").await;
+        let patched = dao
+            .patch(&id, doc! {"email": "renamed@example.com"}, None)
+            .await
+            .unwrap();
+        assert_eq!(patched.version, Some(1));
+    }
+
+    #[tokio::test]
+    async fn login_rehashes_a_plaintext_password() {
+        let dao = user_dao().await;
+        let id = insert_legacy(&dao, "legacy@example.com").await;
+
+        let err = dao.login("legacy@example.com", "wrong").await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(ServerError::Unauthorized(_))
+        ));
+        let err = dao
+            .login("nobody@example.com", "plaintext")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(ServerError::Unauthorized(_))
+        ));
+
+        let user = dao.login("legacy@example.com", "plaintext").await.unwrap();
+        assert_eq!(user.version, Some(1));
 
-        Ok(res)
+        let stored = dao.get(&id).await.unwrap();
+        assert_eq!(stored.verify_password("plaintext").await, Some(true));
+        dao.login("legacy@example.com", "plaintext").await.unwrap();
     }
 }
@@ -1,29 +1,90 @@
-use anyhow::{anyhow as error, Result};
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use validator::Validate;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::auth;
+use crate::auth::permissions::{self, Role};
+use crate::server_errors::ServerError;
+
+pub const MIN_PASSWORD_LENGTH: u64 = 5;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
 pub struct User {
+    #[validate(email)]
     email: String,
+    /// Checked against the plaintext in [`User::new`], stored as an Argon2 hash.
+    #[validate(length(min = "MIN_PASSWORD_LENGTH"))]
     password: String,
+    #[serde(default)]
+    roles: Vec<Role>,
+    #[serde(default)]
+    permissions: Vec<String>,
 }
 
 impl User {
-    pub fn new(email: &str, password: &str) -> Result<Self> {
-        if password.is_empty() {
-            return Err(error!("Password cannot be empty"));
-        }
-
-        if password.len() < 5 {
-            return Err(error!("Password cannot be less than length 5"));
-        }
-
-        if !email.contains("@") || !email.contains(".") {
-            return Err(error!("Email is invalid"));
-        }
-
-        Ok(Self {
+    pub async fn new(email: &str, password: &str) -> Result<Self> {
+        let user = Self {
             email: email.to_string(),
             password: password.to_string(),
+            roles: vec![Role::User],
+            permissions: vec![],
+        };
+        user.validate().map_err(ServerError::from)?;
+
+        Ok(Self {
+            password: auth::hash_password(password).await?,
+            ..user
         })
     }
+
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+
+    /// Checks `password` against the stored Argon2 hash.
+    ///
+    /// Returns `None` when the stored value is not a PHC hash string, i.e. a legacy
+    /// record that still holds the plaintext password.
+    pub async fn verify_password(&self, password: &str) -> Option<bool> {
+        auth::verify_password_hash(password, &self.password).await
+    }
+
+    /// Legacy records stored the raw password. Compares against it directly.
+    pub fn matches_plaintext_password(&self, password: &str) -> bool {
+        !self.password.is_empty() && self.password == password
+    }
+
+    pub async fn set_password(&mut self, password: &str) -> Result<()> {
+        self.password = auth::hash_password(password).await?;
+        Ok(())
+    }
+
+    pub fn password_hash(&self) -> &str {
+        &self.password
+    }
+
+    pub fn roles(&self) -> &[Role] {
+        &self.roles
+    }
+
+    pub fn permissions(&self) -> &[String] {
+        &self.permissions
+    }
+
+    pub fn set_roles(&mut self, roles: Vec<Role>) {
+        self.roles = roles;
+    }
+
+    pub fn set_permissions(&mut self, permissions: Vec<String>) {
+        self.permissions = permissions;
+    }
+
+    /// Permissions from the user's roles together with the directly assigned ones.
+    pub fn effective_permissions(&self) -> Vec<String> {
+        permissions::effective_permissions(&self.roles, &self.permissions)
+    }
+
+    pub fn has_permission(&self, permission: &str) -> bool {
+        permissions::has_permission(&self.effective_permissions(), permission)
+    }
 }
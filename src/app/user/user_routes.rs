@@ -1,74 +1,131 @@
 use axum::{
     extract::{Json, Path, Query, State},
-    http::HeaderMap,
     http::StatusCode,
-    response::{IntoResponse, Response},
-    routing::{get, post},
+    routing::{delete, get, post},
     Router,
 };
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 
-use crate::server_errors::AppError;
+use crate::auth::permissions::{self, require_permission};
+use crate::auth::AuthUser;
+use crate::server_errors::{AppError, ServerError};
+use crate::validation::ValidatedJson;
 use crate::{app::service::Service, server::ServerState};
-use anyhow::anyhow as error;
 use serde::{Deserialize, Serialize};
+use validator::Validate;
 
-use crate::app::dao::DaoObj;
-use crate::app::dto::DTO;
-use crate::app::user::User;
+use crate::app::page::PageRequest;
+use crate::app::user::{User, UserView, MIN_PASSWORD_LENGTH};
+use crate::app::view::{View, ViewPage, Viewer};
 
 use super::user_service;
 
-use serde_json::Value;
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
 pub struct UserCreateRequest {
+    #[validate(email)]
     pub email: String,
+    #[validate(length(min = "MIN_PASSWORD_LENGTH"))]
     pub password: String,
 }
 
 pub async fn user_create(
-    headers: HeaderMap,
+    viewer: Viewer,
     State(state): State<Arc<ServerState>>,
-    Json(payload): Json<UserCreateRequest>,
-) -> Result<Json<DTO<User>>, AppError> {
-    let headers_str = format!("here are the header: {:?}", headers);
-
-    log::info!("header msg: {}", headers_str);
+    ValidatedJson(payload): ValidatedJson<UserCreateRequest>,
+) -> Result<Json<UserView>, AppError> {
     let user_service = state.application_service.user.clone();
-    let data = User::new(payload.email.as_str(), payload.password.as_str())?;
+    let data = User::new(payload.email.as_str(), payload.password.as_str()).await?;
 
     let result = user_service.create(data).await?;
 
-    Ok(result.into())
+    Ok(Json(UserView::view(&result, &viewer)))
 }
 
 pub async fn list_user(
-    headers: HeaderMap,
+    auth: AuthUser,
     State(state): State<Arc<ServerState>>,
-) -> Result<Json<Vec<DTO<User>>>, AppError> {
+    Query(page): Query<PageRequest>,
+    Query(params): Query<Vec<(String, String)>>,
+) -> Result<Json<ViewPage<UserView>>, AppError> {
     let user_service = state.application_service.user.clone();
 
-    let result = user_service.list(1, 10).await?;
+    let query = user_service.query_spec().parse(&params)?;
+    let result = user_service.find(&query, &page).await?;
 
-    Ok(result.into())
+    Ok(Json(result.view(&Viewer::from(&auth.claims))))
 }
 
 pub async fn get_user(
-    headers: HeaderMap,
+    auth: AuthUser,
     State(state): State<Arc<ServerState>>,
     Path(id): Path<String>,
-) -> Result<Json<DTO<User>>, AppError> {
+) -> Result<Json<UserView>, AppError> {
+    // Everyone may read their own record, other users need `user:read`.
+    let is_self = auth.user.id.as_deref() == Some(id.as_str());
+    if !is_self && !permissions::has_permission(&auth.claims.permissions, permissions::USER_READ) {
+        return Err(ServerError::Forbidden(format!(
+            "Missing permission `{}`",
+            permissions::USER_READ
+        ))
+        .into());
+    }
+
     let user_service = state.application_service.user.clone();
 
     let result = user_service.get(&id).await?;
 
-    Ok(result.into())
+    Ok(Json(UserView::view(&result, &Viewer::from(&auth.claims))))
+}
+
+pub async fn delete_user(
+    State(state): State<Arc<ServerState>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    let user_service = state.application_service.user.clone();
+
+    user_service.delete(&id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn list_trashed_user(
+    viewer: Viewer,
+    State(state): State<Arc<ServerState>>,
+    Query(page): Query<PageRequest>,
+) -> Result<Json<ViewPage<UserView>>, AppError> {
+    let user_service = state.application_service.user.clone();
+
+    let result = user_service.list_trashed(&page).await?;
+
+    Ok(Json(result.view(&viewer)))
+}
+
+pub async fn restore_user(
+    viewer: Viewer,
+    State(state): State<Arc<ServerState>>,
+    Path(id): Path<String>,
+) -> Result<Json<UserView>, AppError> {
+    let user_service = state.application_service.user.clone();
+
+    let result = user_service.restore(&id).await?;
+
+    Ok(Json(UserView::view(&result, &viewer)))
+}
+
+pub async fn purge_user(
+    State(state): State<Arc<ServerState>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    let user_service = state.application_service.user.clone();
+
+    user_service.purge(&id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
 }
 
 pub async fn user_login(
     State(state): State<Arc<ServerState>>,
-    Json(payload): Json<user_service::UserLoginRequest>,
+    ValidatedJson(payload): ValidatedJson<user_service::UserLoginRequest>,
 ) -> Result<Json<user_service::UserLoginResponse>, AppError> {
     let user_service = state.application_service.user.clone();
     let result = user_service.login(payload).await?;
@@ -76,10 +133,53 @@ pub async fn user_login(
     Ok(result.into())
 }
 
+pub async fn token_refresh(
+    State(state): State<Arc<ServerState>>,
+    ValidatedJson(payload): ValidatedJson<user_service::RefreshTokenRequest>,
+) -> Result<Json<user_service::TokenResponse>, AppError> {
+    let user_service = state.application_service.user.clone();
+    let result = user_service.refresh_token(payload).await?;
+
+    Ok(result.into())
+}
+
+pub async fn user_logout(
+    State(state): State<Arc<ServerState>>,
+    ValidatedJson(payload): ValidatedJson<user_service::RefreshTokenRequest>,
+) -> Result<StatusCode, AppError> {
+    let user_service = state.application_service.user.clone();
+    user_service.logout(payload).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
 pub fn user_routes() -> Router<Arc<ServerState>> {
     Router::new()
-        .route("/", post(user_create))
-        .route("/", get(list_user))
-        .route("/:user_id", get(get_user))
+        .route(
+            "/",
+            get(list_user)
+                .route_layer(require_permission(permissions::USER_LIST))
+                .post(user_create),
+        )
+        .route(
+            "/:user_id",
+            get(get_user).merge(
+                delete(delete_user).route_layer(require_permission(permissions::USER_DELETE)),
+            ),
+        )
+        .route(
+            "/trash",
+            get(list_trashed_user).route_layer(require_permission(permissions::USER_TRASH)),
+        )
+        .route(
+            "/trash/:user_id",
+            delete(purge_user).route_layer(require_permission(permissions::USER_TRASH)),
+        )
+        .route(
+            "/trash/:user_id/restore",
+            post(restore_user).route_layer(require_permission(permissions::USER_TRASH)),
+        )
         .route("/users/login", post(user_login))
+        .route("/token/refresh", post(token_refresh))
+        .route("/logout", post(user_logout))
 }
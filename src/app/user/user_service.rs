@@ -1,4 +1,4 @@
-use crate::app::user::User;
+use crate::app::user::{User, UserView};
 
 use crate::app::dto::DTO;
 
@@ -7,35 +7,89 @@ use std::sync::Arc;
 
 //use super::UserDao;
 
-use crate::app::dao::DaoObj;
+use crate::app::dao::{DaoError, DaoObj};
+use crate::app::query::{FieldType, QuerySpec};
 use crate::app::service::Service;
 use crate::app::user::user_dao::UserDao;
+use crate::app::view::{View, Viewer};
 
 use crate::auth::generate_token;
+use crate::auth::refresh_token::{RefreshTokenError, RefreshTokenStore};
 
-use crate::secrets;
+use crate::config::AppConfig;
 
 use serde::{Deserialize, Serialize};
+use validator::Validate;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
 pub struct UserLoginRequest {
+    #[validate(length(min = 1))]
     email: String,
+    #[validate(length(min = 1))]
     password: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct UserLoginResponse {
-    pub user: DTO<User>,
+    pub user: UserView,
+    pub token: String,
+    pub refresh_token: String,
+    pub expires_in: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct RefreshTokenRequest {
+    #[validate(length(min = 1))]
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenResponse {
     pub token: String,
+    pub refresh_token: String,
+    pub expires_in: u64,
 }
 
 pub struct UserService {
     dao: Arc<UserDao>,
+    /// Used for the generic `Service` operations, possibly cached.
+    crud_dao: Arc<dyn DaoObj<User>>,
+    refresh_tokens: Arc<RefreshTokenStore>,
+    config: Arc<AppConfig>,
 }
 
 impl UserService {
-    pub fn new(dao: Arc<UserDao>) -> Self {
-        Self { dao }
+    pub fn new(
+        dao: Arc<UserDao>,
+        crud_dao: Arc<dyn DaoObj<User>>,
+        refresh_tokens: Arc<RefreshTokenStore>,
+        config: Arc<AppConfig>,
+    ) -> Self {
+        Self {
+            dao,
+            crud_dao,
+            refresh_tokens,
+            config,
+        }
+    }
+
+    fn access_token(&self, user: &DTO<User>) -> Result<(String, u64)> {
+        let id = user
+            .id
+            .as_ref()
+            .ok_or(error!("id is none. canot generate token"))?;
+
+        let expires_in = self.config.access_token_expiry_minutes * 60;
+
+        let token = generate_token(
+            id,
+            self.config.token_issuer.as_str(),
+            expires_in,
+            user.roles(),
+            &user.effective_permissions(),
+        )?;
+
+        Ok((token, expires_in))
     }
 
     pub async fn find_by_email(&self, email: &str) -> Result<DTO<User>> {
@@ -55,23 +109,163 @@ impl UserService {
             .clone()
             .ok_or(error!("id is none. canot generate token"))?;
 
-        let token = generate_token(
-            &id,
-            secrets::TOKEN_ISSUER.as_str(),
-            secrets::TOKEN_EXPIRY_DAYS.to_string().parse()?,
-        )?;
+        let (token, expires_in) = self.access_token(&result)?;
+        let refresh_token = self.refresh_tokens.issue(&id).await?;
+
+        // The caller is the user that just logged in.
+        let viewer = Viewer {
+            id: Some(id),
+            roles: result.roles().to_vec(),
+        };
 
         let result = UserLoginResponse {
             token,
-            user: result,
+            refresh_token,
+            expires_in,
+            user: UserView::view(&result, &viewer),
         };
 
         Ok(result)
     }
+
+    /// Rotates the refresh token and issues a new access token for its user.
+    pub async fn refresh_token(&self, req: RefreshTokenRequest) -> Result<TokenResponse> {
+        let (record, refresh_token) = self.refresh_tokens.rotate(&req.refresh_token).await?;
+
+        let user = match self.dao.get(&record.user_id).await {
+            Ok(v) => v,
+            Err(e) if matches!(e.downcast_ref(), Some(DaoError::NotFound { .. })) => {
+                // The user is gone, so is every session they had.
+                self.refresh_tokens.revoke_family(&record.family_id).await?;
+                return Err(RefreshTokenError::Revoked.into());
+            }
+            Err(e) => return Err(e),
+        };
+
+        // Roles are re-read on every refresh so permission changes apply within
+        // one access token lifetime.
+        let (token, expires_in) = self.access_token(&user)?;
+
+        Ok(TokenResponse {
+            token,
+            refresh_token,
+            expires_in,
+        })
+    }
+
+    /// Revokes the session the refresh token belongs to.
+    pub async fn logout(&self, req: RefreshTokenRequest) -> Result<()> {
+        self.refresh_tokens.revoke(&req.refresh_token).await?;
+        Ok(())
+    }
 }
 
 impl Service<User> for UserService {
     fn get_dao(&self) -> Arc<dyn DaoObj<User>> {
-        self.dao.clone()
+        self.crud_dao.clone()
+    }
+
+    fn query_spec(&self) -> QuerySpec {
+        QuerySpec::new()
+            .filter("email", FieldType::String)
+            .filter("roles", FieldType::String)
+            .sort("email")
+            .sort("created_at")
+            .sort("updated_at")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::application_factory::ApplicationFactory;
+    use crate::auth::keys;
+    use crate::auth::refresh_token::MemoryTokenBackend;
+
+    async fn user_service() -> UserService {
+        let fac = Arc::new(ApplicationFactory::in_memory());
+        keys::init(&fac.config).unwrap();
+
+        let dao = Arc::new(UserDao::new(fac.clone()).unwrap());
+        dao.init().await.unwrap();
+        let refresh_tokens =
+            RefreshTokenStore::with_backend(Arc::new(MemoryTokenBackend::new()), 60);
+
+        UserService::new(
+            dao.clone(),
+            dao,
+            Arc::new(refresh_tokens),
+            fac.config.clone(),
+        )
+    }
+
+    async fn login(service: &UserService) -> String {
+        let user = User::new("user@example.com", "secret").await.unwrap();
+        service.create(user).await.unwrap();
+        let req = UserLoginRequest {
+            email: "user@example.com".to_string(),
+            password: "secret".to_string(),
+        };
+
+        service.login(req).await.unwrap().refresh_token
+    }
+
+    async fn refresh(service: &UserService, token: &str) -> Result<TokenResponse> {
+        let req = RefreshTokenRequest {
+            refresh_token: token.to_string(),
+        };
+        service.refresh_token(req).await
+    }
+
+    fn refused(result: Result<impl std::fmt::Debug>) -> RefreshTokenError {
+        let err = result.unwrap_err();
+        match err.downcast::<RefreshTokenError>() {
+            Ok(e) => e,
+            Err(e) => panic!("expected a refresh token error, got {:?}", e),
+        }
+    }
+
+    #[tokio::test]
+    async fn reusing_a_rotated_token_revokes_the_family() {
+        let service = user_service().await;
+        let first = login(&service).await;
+
+        let second = refresh(&service, &first).await.unwrap().refresh_token;
+
+        assert!(matches!(
+            refused(refresh(&service, &first).await),
+            RefreshTokenError::Reused
+        ));
+        // The token handed out by the legitimate rotation dies with the family.
+        assert!(matches!(
+            refused(refresh(&service, &second).await),
+            RefreshTokenError::Revoked
+        ));
+    }
+
+    #[tokio::test]
+    async fn logout_invalidates_the_token() {
+        let service = user_service().await;
+        let token = login(&service).await;
+
+        let req = RefreshTokenRequest {
+            refresh_token: token.clone(),
+        };
+        service.logout(req).await.unwrap();
+
+        assert!(matches!(
+            refused(refresh(&service, &token).await),
+            RefreshTokenError::Revoked
+        ));
+    }
+
+    #[tokio::test]
+    async fn unknown_tokens_are_invalid() {
+        let service = user_service().await;
+
+        assert!(matches!(
+            refused(refresh(&service, "not-a-token").await),
+            RefreshTokenError::Invalid
+        ));
     }
 }
@@ -0,0 +1,72 @@
+use serde::Serialize;
+
+use crate::app::dto::DTO;
+use crate::app::user::User;
+use crate::app::view::{View, Viewer};
+use crate::auth::permissions::Role;
+
+/// What clients see of a [`User`]. The password hash is never included, roles
+/// only for admins and the user themselves, directly assigned permissions and
+/// trash state only for admins.
+#[derive(Debug, Clone, Serialize)]
+pub struct UserView {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub email: String,
+    pub created_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub updated_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub roles: Option<Vec<Role>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl View<User> for UserView {
+    fn view(dto: &DTO<User>, viewer: &Viewer) -> Self {
+        let admin = viewer.is_admin();
+        let own = admin || viewer.is_self(dto.id.as_deref());
+
+        Self {
+            id: dto.id.clone(),
+            email: dto.email().to_string(),
+            created_at: dto.created_at,
+            updated_at: dto.updated_at,
+            version: dto.version,
+            roles: own.then(|| dto.roles().to_vec()),
+            permissions: admin.then(|| dto.permissions().to_vec()),
+            deleted_at: if admin { dto.deleted_at } else { None },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn the_password_is_never_serialized() {
+        let mut user = DTO::new(User::new("a@example.com", "secret1").await.unwrap());
+        user.id = Some("6530f1d2a1b2c3d4e5f60718".to_string());
+
+        let own = Viewer {
+            id: user.id.clone(),
+            roles: vec![Role::User],
+        };
+        let admin = Viewer {
+            id: Some("6530f1d2a1b2c3d4e5f60719".to_string()),
+            roles: vec![Role::Admin],
+        };
+
+        for viewer in [own, admin] {
+            let json = serde_json::to_value(UserView::view(&user, &viewer)).unwrap();
+            let json = json.as_object().unwrap();
+
+            assert!(json.contains_key("roles"));
+            assert!(!json.contains_key("password"), "{:?}", json);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::convert::Infallible;
+
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+use serde::Serialize;
+
+use crate::app::dto::DTO;
+use crate::app::page::Page;
+use crate::auth::{self, extractors::bearer_token, permissions::Role, JWTClaims};
+
+/// The caller a view is rendered for.
+///
+/// Taken from the claims verified by `require_permission`, otherwise from a
+/// valid bearer token. Callers without one are anonymous.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Viewer {
+    /// Id of the calling user.
+    pub id: Option<String>,
+    pub roles: Vec<Role>,
+}
+
+impl Viewer {
+    pub fn anonymous() -> Self {
+        Self::default()
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.roles.contains(&Role::Admin)
+    }
+
+    /// Whether the viewer is the document `id` itself, e.g. a user reading
+    /// their own record.
+    pub fn is_self(&self, id: Option<&str>) -> bool {
+        matches!((self.id.as_deref(), id), (Some(a), Some(b)) if a == b)
+    }
+}
+
+impl From<&JWTClaims> for Viewer {
+    fn from(claims: &JWTClaims) -> Self {
+        Self {
+            id: Some(claims.sub.clone()),
+            roles: claims.roles.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Viewer
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(claims) = parts.extensions.get::<JWTClaims>() {
+            return Ok(claims.into());
+        }
+
+        let claims = bearer_token(&parts.headers)
+            .ok()
+            .flatten()
+            .and_then(|token| auth::decode_token(token).ok());
+
+        Ok(claims.as_ref().map(Viewer::from).unwrap_or_default())
+    }
+}
+
+/// Client facing projection of a stored `T`. Handlers return views rather
+/// than the `DTO`, so fields like password hashes never leave the server
+/// while DAOs and services keep working with the full model.
+pub trait View<T>: Serialize {
+    fn view(dto: &DTO<T>, viewer: &Viewer) -> Self;
+}
+
+/// A [`Page`] with its items projected to `V`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ViewPage<V> {
+    pub items: Vec<V>,
+    pub total: u64,
+    pub page: Option<u64>,
+    pub page_size: i64,
+    pub next_cursor: Option<String>,
+    pub prev_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    pub fn view<V: View<T>>(&self, viewer: &Viewer) -> ViewPage<V> {
+        ViewPage {
+            items: self.items.iter().map(|i| V::view(i, viewer)).collect(),
+            total: self.total,
+            page: self.page,
+            page_size: self.page_size,
+            next_cursor: self.next_cursor.clone(),
+            prev_cursor: self.prev_cursor.clone(),
+        }
+    }
+}
@@ -1,34 +1,76 @@
+use std::sync::Arc;
+
+use crate::config::{AppConfig, StorageBackend};
+use crate::persistence::document_store::{DocumentStore, MongoDocumentStore};
+use crate::persistence::memory_store::MemoryDocumentStore;
+use crate::persistence::metered_store::MeteredDocumentStore;
 use crate::persistence::mongo_persistence::MongoProvider;
-use crate::secrets;
+use crate::persistence::sql_provider::SqlProvider;
+use crate::persistence::sql_store::SqlDocumentStore;
 
 use crate::persistence::redis_provider::RedisProvider;
 
 use anyhow::Result;
 
 pub struct ApplicationFactory {
+    pub config: Arc<AppConfig>,
     pub mongo_provider: MongoProvider,
     pub redis_provider: RedisProvider,
+    pub sql_provider: SqlProvider,
+
+    /// Backend every `DaoObj` reads and writes through.
+    pub store: Arc<dyn DocumentStore>,
 }
 
 impl ApplicationFactory {
-    pub async fn new() -> Result<Self> {
-        let mongo_uri = secrets::MONGO_URI.to_string();
-        let mongo_database = secrets::MONGO_DATABASE.to_string();
-
-        let mut mongo_provider = MongoProvider::new(&mongo_uri, &mongo_database);
+    pub async fn new(config: Arc<AppConfig>) -> Result<Self> {
+        let mut mongo_provider = MongoProvider::new(&config.mongo_uri, &config.mongo_database);
+        let mut sql_provider = SqlProvider::new(&config.sql_uri);
 
-        mongo_provider.connect().await?;
-        log::info!("Mongo Connected!!");
+        let store: Arc<dyn DocumentStore> = match config.storage_backend {
+            StorageBackend::Mongo => {
+                mongo_provider.connect().await?;
+                log::info!("Mongo Connected!!");
+                Arc::new(MongoDocumentStore::new(mongo_provider.clone()))
+            }
+            StorageBackend::Sql => {
+                sql_provider.connect().await?;
+                Arc::new(SqlDocumentStore::new(
+                    sql_provider.clone(),
+                    config.sql_id_type,
+                ))
+            }
+            StorageBackend::Memory => {
+                log::warn!("Using the in-memory storage backend, data is not persisted");
+                Arc::new(MemoryDocumentStore::new())
+            }
+        };
+        let store = Arc::new(MeteredDocumentStore::new(store));
 
-        let redis_uri = secrets::REDIS_URI.to_string();
-        let mut redis_provider = RedisProvider::new(redis_uri.as_str());
+        let mut redis_provider = RedisProvider::new(&config.redis_uri);
         redis_provider.connect().await?;
 
         log::info!("Redis connected!!");
 
         Ok(Self {
+            config,
             mongo_provider,
             redis_provider,
+            sql_provider,
+            store,
         })
     }
+
+    /// Factory backed by [`MemoryDocumentStore`] without connecting to Mongo or
+    /// Redis. DAOs work as usual; transactions, migrations and refresh tokens
+    /// need the real providers and fail.
+    pub fn in_memory() -> Self {
+        Self {
+            config: Arc::new(AppConfig::default()),
+            mongo_provider: MongoProvider::new("", ""),
+            redis_provider: RedisProvider::new(""),
+            sql_provider: SqlProvider::new(""),
+            store: Arc::new(MemoryDocumentStore::new()),
+        }
+    }
 }
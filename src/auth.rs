@@ -1,10 +1,9 @@
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 
-use crate::secrets;
 use crate::utils;
 
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use jsonwebtoken::{decode, decode_header, encode, Validation};
 
 use argon2::{
     password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
@@ -12,16 +11,34 @@ use argon2::{
 };
 
 use anyhow::{anyhow as error, Result};
+use once_cell::sync::Lazy;
 
-#[derive(Debug, Serialize, Deserialize)]
+pub mod extractors;
+pub mod keys;
+pub mod permissions;
+pub mod refresh_token;
+
+pub use extractors::{AuthUser, OptionalAuthUser};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JWTClaims {
     pub exp: usize, // Required (validate_exp defaults to true in validation). Expiration time (as UTC timestamp)
     pub iat: usize, // Optional. Issued at (as UTC timestamp)
     pub iss: String, // Optional. Issuer
     pub sub: String, // Optional. Subject (whom token refers to)
+    #[serde(default)]
+    pub roles: Vec<permissions::Role>,
+    #[serde(default)]
+    pub permissions: Vec<String>,
 }
 
-pub fn generate_token(subject: &str, issuer: &str, expiry_days: u64) -> Result<String> {
+pub fn generate_token(
+    subject: &str,
+    issuer: &str,
+    expiry_seconds: u64,
+    roles: &[permissions::Role],
+    permissions: &[String],
+) -> Result<String> {
     let utils::SECONDS(elasped) = match utils::get_current_timestamp() {
         Err(e) => return Err(error!("{}", e.to_string())),
         Ok(v) => v,
@@ -32,10 +49,7 @@ pub fn generate_token(subject: &str, issuer: &str, expiry_days: u64) -> Result<S
         Ok(v) => v,
     };
 
-    let seconds_in_day: u64 = 24 * 60 * 60;
-    let seconds_total: u64 = seconds_in_day * expiry_days;
-
-    let seconds_total = match usize::try_from(seconds_total) {
+    let seconds_total = match usize::try_from(expiry_seconds) {
         Err(e) => return Err(error!("{}", e.to_string())),
         Ok(v) => v,
     };
@@ -47,13 +61,13 @@ pub fn generate_token(subject: &str, issuer: &str, expiry_days: u64) -> Result<S
         sub: subject.to_string(),
         exp: exp_elasped,
         iss: issuer.to_string(),
+        roles: roles.to_vec(),
+        permissions: permissions.to_vec(),
     };
 
-    let token = match encode(
-        &Header::default(),
-        &claim,
-        &EncodingKey::from_secret(secrets::SESSION_KEY.as_ref()),
-    ) {
+    let keys = keys::key_set()?;
+
+    let token = match encode(&keys.header(), &claim, keys.encoding_key()) {
         Err(e) => return Err(error!("{}", e.to_string())),
         Ok(v) => v,
     };
@@ -62,11 +76,12 @@ pub fn generate_token(subject: &str, issuer: &str, expiry_days: u64) -> Result<S
 }
 
 pub fn decode_token(token: String) -> Result<JWTClaims> {
-    let token = match decode::<JWTClaims>(
-        &token,
-        &DecodingKey::from_secret(secrets::SESSION_KEY.as_ref()),
-        &Validation::default(),
-    ) {
+    let header = decode_header(&token)
+        .map_err(|e| error!("Unable to decode token header: {}", e.to_string()))?;
+
+    let (key, algorithm) = keys::key_set()?.decoding_key(&header)?;
+
+    let token = match decode::<JWTClaims>(&token, key, &Validation::new(algorithm)) {
         Err(e) => return Err(error!("Unable to decode token: {}", e.to_string())),
         Ok(v) => v,
     };
@@ -76,21 +91,20 @@ pub fn decode_token(token: String) -> Result<JWTClaims> {
 }
 
 pub fn verify_token(token: &str) -> Result<bool> {
-    let mut validation = Validation::default();
-    validation.validate_exp = true;
-    validation.insecure_disable_signature_validation();
+    decode_token(token.to_string())?;
 
-    let dummy_key = DecodingKey::from_secret(&[]);
+    Ok(true)
+}
 
-    let _data = match decode::<JWTClaims>(token, &dummy_key, &validation) {
-        Ok(v) => v,
-        Err(e) => return Err(error!("Unable to decode token: {}", e.to_string())),
-    };
+/// Hashes `phrase` on the blocking pool; argon2 is deliberately slow and would
+/// otherwise stall the async worker it runs on.
+pub async fn hash_password(phrase: &str) -> Result<String> {
+    let phrase = phrase.to_string();
 
-    Ok(true)
+    tokio::task::spawn_blocking(move || hash_password_blocking(&phrase)).await?
 }
 
-pub fn hash_password(phrase: &str) -> Result<String> {
+fn hash_password_blocking(phrase: &str) -> Result<String> {
     let password = phrase.as_bytes();
 
     let salt = SaltString::generate(&mut OsRng);
@@ -107,11 +121,38 @@ pub fn hash_password(phrase: &str) -> Result<String> {
     Ok(password_hash)
 }
 
-pub fn verify_password_hash(phrase: &str, hash: &str) -> Option<bool> {
-    let parsed_hash = PasswordHash::new(&hash).ok()?;
+/// Checks `phrase` against a PHC `hash` on the blocking pool. `None` when `hash`
+/// is not a PHC string at all; a verify task that fails to finish counts as a
+/// mismatch.
+pub async fn verify_password_hash(phrase: &str, hash: &str) -> Option<bool> {
+    let phrase = phrase.to_string();
+    let hash = hash.to_string();
+
+    tokio::task::spawn_blocking(move || verify_password_hash_blocking(&phrase, &hash))
+        .await
+        .unwrap_or(Some(false))
+}
+
+fn verify_password_hash_blocking(phrase: &str, hash: &str) -> Option<bool> {
+    let parsed_hash = PasswordHash::new(hash).ok()?;
     let is_ok = Argon2::default()
         .verify_password(phrase.as_bytes(), &parsed_hash)
         .is_ok();
 
     Some(is_ok)
 }
+
+/// Hash verified against when a login names no stored password, so those
+/// attempts cost the same argon2 work as a wrong password.
+static DUMMY_HASH: Lazy<String> =
+    Lazy::new(|| hash_password_blocking("dummy password").expect("Failed to hash dummy password"));
+
+/// Runs an argon2 verify whose result is discarded, to keep the response time
+/// of unknown accounts in line with known ones.
+pub async fn verify_dummy_password(phrase: &str) {
+    let phrase = phrase.to_string();
+
+    let _ =
+        tokio::task::spawn_blocking(move || verify_password_hash_blocking(&phrase, &DUMMY_HASH))
+            .await;
+}
@@ -0,0 +1,108 @@
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header::AUTHORIZATION, request::Parts, HeaderMap},
+};
+
+use crate::app::dao::DaoError;
+use crate::app::dto::DTO;
+use crate::app::service::Service;
+use crate::app::user::User;
+use crate::auth::{self, JWTClaims};
+use crate::server::ServerState;
+use crate::server_errors::ServerError;
+
+/// Authenticated caller resolved from the `Authorization: Bearer <token>` header.
+///
+/// Take it as a handler parameter to require a valid token:
+///
+/// ```ignore
+/// async fn handler(AuthUser { user, .. }: AuthUser) -> ... {}
+/// ```
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub user: DTO<User>,
+    pub claims: JWTClaims,
+}
+
+/// Same as [`AuthUser`] but resolves to `None` when no `Authorization` header is sent.
+/// A header carrying an invalid token is still rejected.
+#[derive(Debug, Clone)]
+pub struct OptionalAuthUser(pub Option<AuthUser>);
+
+/// Extracts the token from an `Authorization` header. Accepts both `Bearer <token>`
+/// and a bare `<token>`. Returns `Ok(None)` when the header is absent.
+pub fn bearer_token(headers: &HeaderMap) -> Result<Option<String>, ServerError> {
+    let authorization = match headers.get(AUTHORIZATION) {
+        Some(v) => v
+            .to_str()
+            .map_err(|e| ServerError::Unauthorized(format!("No token in header: {}", e)))?,
+        None => return Ok(None),
+    };
+
+    let mut parts = authorization.split_whitespace();
+    let token = match (parts.next(), parts.next()) {
+        (Some(scheme), Some(token)) if scheme.eq_ignore_ascii_case("bearer") => token,
+        (Some(token), None) => token,
+        _ => {
+            return Err(ServerError::Unauthorized(
+                "Malformed authorization header".to_string(),
+            ))
+        }
+    };
+
+    Ok(Some(token.to_string()))
+}
+
+async fn authenticate(token: String, state: &ServerState) -> Result<AuthUser, ServerError> {
+    let claims = auth::decode_token(token)
+        .map_err(|e| ServerError::Unauthorized(format!("Auth token unable to decode: {}", e)))?;
+
+    let user = state
+        .application_service
+        .user
+        .get(&claims.sub)
+        .await
+        .map_err(|e| match e.downcast_ref::<DaoError>() {
+            // A valid token for a user that is gone; don't tell the caller which.
+            Some(DaoError::NotFound { .. }) => {
+                ServerError::Unauthorized("Invalid auth token".to_string())
+            }
+            _ => ServerError::classify(&e),
+        })?;
+
+    Ok(AuthUser { user, claims })
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<ServerState>> for AuthUser {
+    type Rejection = ServerError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<ServerState>,
+    ) -> Result<Self, Self::Rejection> {
+        let token = bearer_token(&parts.headers)?.ok_or(ServerError::Unauthorized(
+            "Not authorized. token not found".to_string(),
+        ))?;
+
+        authenticate(token, state).await
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<ServerState>> for OptionalAuthUser {
+    type Rejection = ServerError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<ServerState>,
+    ) -> Result<Self, Self::Rejection> {
+        match bearer_token(&parts.headers)? {
+            Some(token) => Ok(Self(Some(authenticate(token, state).await?))),
+            None => Ok(Self(None)),
+        }
+    }
+}
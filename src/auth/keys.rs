@@ -0,0 +1,525 @@
+use anyhow::{anyhow as error, Result};
+use axum::Json;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters,
+        EllipticCurveKeyType, Jwk, JwkSet, OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+        RSAKeyParameters, RSAKeyType,
+    },
+    Algorithm, DecodingKey, EncodingKey, Header,
+};
+use once_cell::sync::OnceCell;
+use simple_asn1::{oid, ASN1Block};
+
+use crate::config::AppConfig;
+use crate::server_errors::ServerError;
+
+/// Key used to sign newly issued tokens.
+pub struct SigningKey {
+    pub kid: Option<String>,
+    pub algorithm: Algorithm,
+    key: EncodingKey,
+}
+
+/// Key accepted when verifying tokens. Retired signing keys stay here until
+/// every token they signed has expired.
+pub struct VerificationKey {
+    pub kid: Option<String>,
+    pub algorithm: Algorithm,
+    key: DecodingKey,
+    jwk: Option<Jwk>,
+}
+
+pub struct KeySet {
+    pub signing: SigningKey,
+    pub verification: Vec<VerificationKey>,
+}
+
+static KEY_SET: OnceCell<KeySet> = OnceCell::new();
+
+/// Loads the process wide key set from `config`. Later calls return the keys
+/// loaded first.
+pub fn init(config: &AppConfig) -> Result<&'static KeySet> {
+    KEY_SET.get_or_try_init(|| KeySet::from_config(config))
+}
+
+/// Returns the process wide key set, see [`init`].
+pub fn key_set() -> Result<&'static KeySet> {
+    KEY_SET.get().ok_or(error!(
+        "JWT keys are not loaded, call `keys::init` at startup"
+    ))
+}
+
+fn is_hmac(algorithm: Algorithm) -> bool {
+    matches!(
+        algorithm,
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+    )
+}
+
+fn is_rsa(algorithm: Algorithm) -> bool {
+    matches!(
+        algorithm,
+        Algorithm::RS256
+            | Algorithm::RS384
+            | Algorithm::RS512
+            | Algorithm::PS256
+            | Algorithm::PS384
+            | Algorithm::PS512
+    )
+}
+
+impl KeySet {
+    /// Loads keys from:
+    /// - `jwt_algorithm` signing algorithm, defaults to `HS256`
+    /// - `jwt_key_id` optional `kid` of the active signing key
+    /// - `jwt_private_key_file` PEM private key, required for asymmetric algorithms
+    /// - `jwt_public_keys` comma separated `kid=path` PEM public keys accepted for verification.
+    ///   Must include the public half of `jwt_key_id`
+    ///
+    /// HMAC algorithms sign and verify with `session_key`.
+    pub fn from_config(config: &AppConfig) -> Result<Self> {
+        let algorithm = config.jwt_algorithm;
+        let kid = config.jwt_key_id.clone();
+
+        if is_hmac(algorithm) {
+            let secret = config.session_key.as_bytes();
+            return Ok(Self {
+                signing: SigningKey {
+                    kid: kid.clone(),
+                    algorithm,
+                    key: EncodingKey::from_secret(secret),
+                },
+                verification: vec![VerificationKey {
+                    kid,
+                    algorithm,
+                    key: DecodingKey::from_secret(secret),
+                    jwk: None,
+                }],
+            });
+        }
+
+        let kid = kid.ok_or(error!("jwt_key_id is required for {:?}", algorithm))?;
+
+        let private_key_file = config.jwt_private_key_file.as_ref().ok_or(error!(
+            "jwt_private_key_file is required for {:?}",
+            algorithm
+        ))?;
+        let private_pem = std::fs::read(private_key_file)
+            .map_err(|e| error!("Unable to read {}: {}", private_key_file, e))?;
+
+        let key = match algorithm {
+            a if is_rsa(a) => EncodingKey::from_rsa_pem(&private_pem)?,
+            Algorithm::ES256 | Algorithm::ES384 => EncodingKey::from_ec_pem(&private_pem)?,
+            Algorithm::EdDSA => EncodingKey::from_ed_pem(&private_pem)?,
+            a => return Err(error!("Unsupported signing algorithm {:?}", a)),
+        };
+
+        let mut verification = Vec::new();
+        for (key_id, path) in config.jwt_public_keys.iter() {
+            let pem = std::fs::read(path).map_err(|e| error!("Unable to read {}: {}", path, e))?;
+
+            verification.push(VerificationKey::from_public_pem(key_id, &pem, algorithm)?);
+        }
+
+        if !verification
+            .iter()
+            .any(|k| k.kid.as_deref() == Some(kid.as_str()))
+        {
+            return Err(error!(
+                "jwt_public_keys does not contain the public key for jwt_key_id `{}`",
+                kid
+            ));
+        }
+
+        Ok(Self {
+            signing: SigningKey {
+                kid: Some(kid),
+                algorithm,
+                key,
+            },
+            verification,
+        })
+    }
+
+    pub fn header(&self) -> Header {
+        let mut header = Header::new(self.signing.algorithm);
+        header.kid = self.signing.kid.clone();
+        header
+    }
+
+    pub fn encoding_key(&self) -> &EncodingKey {
+        &self.signing.key
+    }
+
+    /// Picks the verification key for a token header. Tokens without a `kid`
+    /// are checked against the active signing key.
+    pub fn decoding_key(&self, header: &Header) -> Result<(&DecodingKey, Algorithm)> {
+        let kid = header.kid.as_ref().or(self.signing.kid.as_ref());
+
+        let key = self
+            .verification
+            .iter()
+            .find(|k| k.kid.as_ref() == kid)
+            .ok_or(error!("No verification key found for kid {:?}", header.kid))?;
+
+        Ok((&key.key, key.algorithm))
+    }
+
+    /// Public verification keys. Symmetric keys are never published.
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self
+                .verification
+                .iter()
+                .filter_map(|k| k.jwk.clone())
+                .collect(),
+        }
+    }
+}
+
+fn b64(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn unsigned_bytes(block: &ASN1Block) -> Result<Vec<u8>> {
+    match block {
+        ASN1Block::Integer(_, v) => {
+            let (_, bytes) = v.to_bytes_be();
+            Ok(bytes)
+        }
+        _ => Err(error!("Expected an ASN.1 integer")),
+    }
+}
+
+fn rsa_parameters(der: &[u8]) -> Result<AlgorithmParameters> {
+    let blocks = simple_asn1::from_der(der)?;
+    match blocks.first() {
+        Some(ASN1Block::Sequence(_, items)) if items.len() == 2 => {
+            Ok(AlgorithmParameters::RSA(RSAKeyParameters {
+                key_type: RSAKeyType::RSA,
+                n: b64(&unsigned_bytes(&items[0])?),
+                e: b64(&unsigned_bytes(&items[1])?),
+            }))
+        }
+        _ => Err(error!("Invalid RSA public key")),
+    }
+}
+
+/// Reads a `PUBLIC KEY` (SubjectPublicKeyInfo) or `RSA PUBLIC KEY` (PKCS#1) PEM
+/// into its JWK parameters along with the default algorithm for the key type.
+fn public_key_parameters(pem: &[u8]) -> Result<(AlgorithmParameters, Algorithm)> {
+    let pem = pem::parse(pem)?;
+
+    if pem.tag == "RSA PUBLIC KEY" {
+        return Ok((rsa_parameters(&pem.contents)?, Algorithm::RS256));
+    }
+
+    if pem.tag != "PUBLIC KEY" {
+        return Err(error!(
+            "Unsupported PEM `{}`, expected a public key",
+            pem.tag
+        ));
+    }
+
+    let blocks = simple_asn1::from_der(&pem.contents)?;
+    let (identifier, key) = match blocks.first() {
+        Some(ASN1Block::Sequence(_, items)) => match (items.first(), items.get(1)) {
+            (Some(ASN1Block::Sequence(_, identifier)), Some(ASN1Block::BitString(_, _, key))) => {
+                (identifier, key)
+            }
+            _ => return Err(error!("Invalid SubjectPublicKeyInfo")),
+        },
+        _ => return Err(error!("Invalid SubjectPublicKeyInfo")),
+    };
+
+    let key_oid = match identifier.first() {
+        Some(ASN1Block::ObjectIdentifier(_, oid)) => oid,
+        _ => return Err(error!("Public key algorithm identifier not found")),
+    };
+
+    if key_oid == oid!(1, 2, 840, 113549, 1, 1, 1) {
+        return Ok((rsa_parameters(key)?, Algorithm::RS256));
+    }
+
+    if key_oid == oid!(1, 3, 101, 112) {
+        return Ok((
+            AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: b64(key),
+            }),
+            Algorithm::EdDSA,
+        ));
+    }
+
+    if key_oid == oid!(1, 2, 840, 10045, 2, 1) {
+        let (curve, algorithm, size) = match identifier.get(1) {
+            Some(ASN1Block::ObjectIdentifier(_, c)) if c == oid!(1, 2, 840, 10045, 3, 1, 7) => {
+                (EllipticCurve::P256, Algorithm::ES256, 32)
+            }
+            Some(ASN1Block::ObjectIdentifier(_, c)) if c == oid!(1, 3, 132, 0, 34) => {
+                (EllipticCurve::P384, Algorithm::ES384, 48)
+            }
+            _ => return Err(error!("Unsupported elliptic curve")),
+        };
+
+        // Uncompressed point: 0x04 || x || y
+        if key.len() != 1 + 2 * size || key[0] != 0x04 {
+            return Err(error!("Only uncompressed EC public keys are supported"));
+        }
+
+        return Ok((
+            AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                key_type: EllipticCurveKeyType::EC,
+                curve,
+                x: b64(&key[1..1 + size]),
+                y: b64(&key[1 + size..]),
+            }),
+            algorithm,
+        ));
+    }
+
+    Err(error!("Unsupported public key algorithm"))
+}
+
+impl VerificationKey {
+    /// `preferred` is used when it fits the key type, so RSA keys can verify
+    /// RS384/PS256 etc. Otherwise the key type default applies.
+    pub fn from_public_pem(kid: &str, pem: &[u8], preferred: Algorithm) -> Result<Self> {
+        let (parameters, default_algorithm) = public_key_parameters(pem)?;
+
+        let algorithm = match (&parameters, preferred) {
+            (AlgorithmParameters::RSA(_), a) if is_rsa(a) => a,
+            _ => default_algorithm,
+        };
+
+        let jwk = Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                algorithm: Some(algorithm),
+                key_id: Some(kid.to_string()),
+                ..Default::default()
+            },
+            algorithm: parameters,
+        };
+
+        Ok(Self {
+            kid: Some(kid.to_string()),
+            algorithm,
+            key: DecodingKey::from_jwk(&jwk)?,
+            jwk: Some(jwk),
+        })
+    }
+}
+
+/// `GET /.well-known/jwks.json`
+pub async fn jwks() -> Result<Json<JwkSet>, ServerError> {
+    let keys = key_set().map_err(|e| ServerError::Internal(e.to_string()))?;
+    Ok(Json(keys.jwks()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testdata(name: &str) -> String {
+        format!("{}/src/auth/testdata/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    fn pem(name: &str) -> Vec<u8> {
+        std::fs::read(testdata(name)).unwrap()
+    }
+
+    fn decoded_len(value: &str) -> usize {
+        URL_SAFE_NO_PAD.decode(value).unwrap().len()
+    }
+
+    /// Key set signing with `private` as `current`, also accepting `ec256_public.pem` as `old`.
+    fn key_set(algorithm: Algorithm, private: &str, public: &str) -> KeySet {
+        let config = AppConfig {
+            jwt_algorithm: algorithm,
+            jwt_key_id: Some("current".to_string()),
+            jwt_private_key_file: Some(testdata(private)),
+            jwt_public_keys: vec![
+                ("old".to_string(), testdata("ec256_public.pem")),
+                ("current".to_string(), testdata(public)),
+            ],
+            ..AppConfig::default()
+        };
+
+        KeySet::from_config(&config).unwrap()
+    }
+
+    fn round_trip(keys: &KeySet) {
+        let claims = serde_json::json!({"sub": "user", "exp": 4_000_000_000u64});
+        let token = jsonwebtoken::encode(&keys.header(), &claims, keys.encoding_key()).unwrap();
+
+        let header = jsonwebtoken::decode_header(&token).unwrap();
+        assert_eq!(header.kid, keys.signing.kid);
+
+        let (key, algorithm) = keys.decoding_key(&header).unwrap();
+        let decoded = jsonwebtoken::decode::<serde_json::Value>(
+            &token,
+            key,
+            &jsonwebtoken::Validation::new(algorithm),
+        )
+        .unwrap();
+        assert_eq!(decoded.claims["sub"], "user");
+    }
+
+    #[test]
+    fn parses_rsa_public_keys() {
+        let (spki, algorithm) = public_key_parameters(&pem("rsa_public.pem")).unwrap();
+        let (pkcs1, _) = public_key_parameters(&pem("rsa_pkcs1_public.pem")).unwrap();
+
+        assert_eq!(algorithm, Algorithm::RS256);
+        match (spki, pkcs1) {
+            (AlgorithmParameters::RSA(spki), AlgorithmParameters::RSA(pkcs1)) => {
+                assert_eq!(decoded_len(&spki.n), 256);
+                assert_eq!(spki.e, "AQAB");
+                assert_eq!(spki.n, pkcs1.n);
+                assert_eq!(spki.e, pkcs1.e);
+            }
+            other => panic!("expected RSA parameters, got {:?}", other),
+        }
+
+        // RSA keys keep any RSA algorithm they are configured with.
+        let key = VerificationKey::from_public_pem("k", &pem("rsa_public.pem"), Algorithm::PS384)
+            .unwrap();
+        assert_eq!(key.algorithm, Algorithm::PS384);
+    }
+
+    #[test]
+    fn parses_ec_public_keys() {
+        for (file, curve, algorithm, size) in [
+            (
+                "ec256_public.pem",
+                EllipticCurve::P256,
+                Algorithm::ES256,
+                32,
+            ),
+            (
+                "ec384_public.pem",
+                EllipticCurve::P384,
+                Algorithm::ES384,
+                48,
+            ),
+        ] {
+            let (parameters, default_algorithm) = public_key_parameters(&pem(file)).unwrap();
+
+            assert_eq!(default_algorithm, algorithm);
+            match parameters {
+                AlgorithmParameters::EllipticCurve(ec) => {
+                    assert_eq!(ec.curve, curve);
+                    assert_eq!(decoded_len(&ec.x), size);
+                    assert_eq!(decoded_len(&ec.y), size);
+                }
+                other => panic!("expected EC parameters, got {:?}", other),
+            }
+        }
+
+        // The curve decides the algorithm, not the configured one.
+        let key = VerificationKey::from_public_pem("k", &pem("ec384_public.pem"), Algorithm::RS256)
+            .unwrap();
+        assert_eq!(key.algorithm, Algorithm::ES384);
+    }
+
+    #[test]
+    fn parses_ed25519_public_keys() {
+        let (parameters, algorithm) = public_key_parameters(&pem("ed25519_public.pem")).unwrap();
+
+        assert_eq!(algorithm, Algorithm::EdDSA);
+        match parameters {
+            AlgorithmParameters::OctetKeyPair(okp) => {
+                assert_eq!(okp.curve, EllipticCurve::Ed25519);
+                assert_eq!(decoded_len(&okp.x), 32);
+            }
+            other => panic!("expected OKP parameters, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_keys_that_are_not_public() {
+        let err = public_key_parameters(&pem("rsa_private.pem")).unwrap_err();
+        assert!(err.to_string().contains("expected a public key"), "{}", err);
+
+        assert!(public_key_parameters(b"not a pem").is_err());
+    }
+
+    #[test]
+    fn signs_and_verifies_with_every_key_type() {
+        for (algorithm, private, public) in [
+            (Algorithm::RS256, "rsa_private.pem", "rsa_public.pem"),
+            (Algorithm::ES256, "ec256_private.pem", "ec256_public.pem"),
+            (Algorithm::ES384, "ec384_private.pem", "ec384_public.pem"),
+            (
+                Algorithm::EdDSA,
+                "ed25519_private.pem",
+                "ed25519_public.pem",
+            ),
+        ] {
+            round_trip(&key_set(algorithm, private, public));
+        }
+    }
+
+    #[test]
+    fn jwks_publishes_every_verification_key() {
+        let keys = key_set(
+            Algorithm::EdDSA,
+            "ed25519_private.pem",
+            "ed25519_public.pem",
+        );
+        let jwks = serde_json::to_value(keys.jwks()).unwrap();
+
+        let keys = jwks["keys"].as_array().unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0]["kid"], "old");
+        assert_eq!(keys[0]["kty"], "EC");
+        assert_eq!(keys[0]["crv"], "P-256");
+        assert_eq!(keys[0]["alg"], "ES256");
+        assert_eq!(keys[1]["kid"], "current");
+        assert_eq!(keys[1]["kty"], "OKP");
+        assert_eq!(keys[1]["crv"], "Ed25519");
+        assert_eq!(keys[1]["alg"], "EdDSA");
+        for key in keys {
+            assert_eq!(key["use"], "sig");
+            assert!(key.get("d").is_none(), "private part published: {}", key);
+        }
+    }
+
+    #[test]
+    fn hmac_secrets_are_not_published() {
+        let config = AppConfig {
+            session_key: "secret".to_string(),
+            ..AppConfig::default()
+        };
+        let keys = KeySet::from_config(&config).unwrap();
+
+        assert!(keys.jwks().keys.is_empty());
+        round_trip(&keys);
+    }
+
+    #[test]
+    fn decoding_key_is_picked_by_kid() {
+        let keys = key_set(Algorithm::RS256, "rsa_private.pem", "rsa_public.pem");
+
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some("old".to_string());
+        let (_, algorithm) = keys.decoding_key(&header).unwrap();
+        assert_eq!(algorithm, Algorithm::ES256);
+
+        // No kid falls back to the signing key.
+        header.kid = None;
+        let (_, algorithm) = keys.decoding_key(&header).unwrap();
+        assert_eq!(algorithm, Algorithm::RS256);
+
+        header.kid = Some("unknown".to_string());
+        let err = match keys.decoding_key(&header) {
+            Ok(_) => panic!("unknown kid resolved to a key"),
+            Err(e) => e,
+        };
+        assert!(err.to_string().contains("unknown"), "{}", err);
+    }
+}
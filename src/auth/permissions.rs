@@ -0,0 +1,201 @@
+use std::task::{Context, Poll};
+
+use axum::{
+    http::Request,
+    response::{IntoResponse, Response},
+};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use tower::{Layer, Service};
+
+use crate::auth::{self, extractors::bearer_token, JWTClaims};
+use crate::server_errors::ServerError;
+
+pub const USER_CREATE: &str = "user:create";
+pub const USER_LIST: &str = "user:list";
+pub const USER_READ: &str = "user:read";
+pub const USER_UPDATE: &str = "user:update";
+pub const USER_DELETE: &str = "user:delete";
+pub const USER_TRASH: &str = "user:trash";
+
+#[derive(
+    Debug, Clone, PartialEq, Eq, Serialize, Deserialize, strum::Display, strum::EnumString,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum Role {
+    Admin,
+    User,
+}
+
+impl Role {
+    /// Permissions granted by the role on top of those assigned to the user directly.
+    pub fn permissions(&self) -> &'static [&'static str] {
+        match self {
+            Role::Admin => &["*"],
+            Role::User => &[],
+        }
+    }
+}
+
+/// Union of the permissions granted by `roles` and the directly assigned `permissions`.
+pub fn effective_permissions(roles: &[Role], permissions: &[String]) -> Vec<String> {
+    let mut result: Vec<String> = roles
+        .iter()
+        .flat_map(|r| r.permissions().iter().map(|p| p.to_string()))
+        .chain(permissions.iter().cloned())
+        .collect();
+
+    result.sort();
+    result.dedup();
+    result
+}
+
+/// Checks `required` (e.g. `user:list`) against granted permissions.
+/// `*` grants everything and `user:*` grants every `user:` permission.
+pub fn has_permission(granted: &[String], required: &str) -> bool {
+    granted.iter().any(|p| {
+        if p == "*" || p == required {
+            return true;
+        }
+
+        match p.strip_suffix('*') {
+            Some(prefix) if prefix.ends_with(':') => required.starts_with(prefix),
+            _ => false,
+        }
+    })
+}
+
+/// Route layer rejecting requests whose token does not carry `permission`.
+///
+/// ```ignore
+/// Router::new().route("/", get(list_user).route_layer(require_permission("user:list")))
+/// ```
+///
+/// The decoded [`JWTClaims`] are added to the request extensions.
+pub fn require_permission(permission: &'static str) -> RequirePermissionLayer {
+    RequirePermissionLayer { permission }
+}
+
+#[derive(Debug, Clone)]
+pub struct RequirePermissionLayer {
+    permission: &'static str,
+}
+
+impl<S> Layer<S> for RequirePermissionLayer {
+    type Service = RequirePermission<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequirePermission {
+            inner,
+            permission: self.permission,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RequirePermission<S> {
+    inner: S,
+    permission: &'static str,
+}
+
+fn authorize<B>(req: &Request<B>, permission: &str) -> Result<JWTClaims, ServerError> {
+    let token = bearer_token(req.headers())?.ok_or(ServerError::Unauthorized(
+        "Not authorized. token not found".to_string(),
+    ))?;
+
+    let claims = auth::decode_token(token)
+        .map_err(|e| ServerError::Unauthorized(format!("Auth token unable to decode: {}", e)))?;
+
+    if !has_permission(&claims.permissions, permission) {
+        return Err(ServerError::Forbidden(format!(
+            "Missing permission `{}`",
+            permission
+        )));
+    }
+
+    Ok(claims)
+}
+
+impl<S, B> Service<Request<B>> for RequirePermission<S>
+where
+    S: Service<Request<B>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        // Take the service that was driven to readiness and leave a clone behind.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        match authorize(&req, self.permission) {
+            Ok(claims) => {
+                req.extensions_mut().insert(claims);
+                Box::pin(inner.call(req))
+            }
+            Err(e) => Box::pin(async move { Ok(e.into_response()) }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn granted(permissions: &[&str]) -> Vec<String> {
+        permissions.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn star_grants_everything() {
+        let granted = granted(&["*"]);
+
+        assert!(has_permission(&granted, USER_READ));
+        assert!(has_permission(&granted, "todo:delete"));
+    }
+
+    #[test]
+    fn prefix_wildcards_stay_within_their_prefix() {
+        let granted = granted(&["user:*"]);
+
+        assert!(has_permission(&granted, USER_READ));
+        assert!(has_permission(&granted, USER_TRASH));
+        assert!(!has_permission(&granted, "todo:read"));
+        // `user:*` is not `user*`.
+        assert!(!has_permission(&granted, "username:read"));
+    }
+
+    #[test]
+    fn only_trailing_colon_wildcards_match_prefixes() {
+        assert!(!has_permission(&granted(&["user*"]), USER_READ));
+        assert!(!has_permission(&granted(&["*:read"]), USER_READ));
+        assert!(!has_permission(&granted(&["us:*"]), USER_READ));
+    }
+
+    #[test]
+    fn exact_permissions_match_only_themselves() {
+        let granted = granted(&[USER_READ]);
+
+        assert!(has_permission(&granted, USER_READ));
+        assert!(!has_permission(&granted, USER_UPDATE));
+        assert!(!has_permission(&[], USER_READ));
+    }
+
+    #[test]
+    fn admin_role_grants_everything_user_role_nothing() {
+        let admin = effective_permissions(&[Role::Admin], &[]);
+        let user = effective_permissions(&[Role::User], &granted(&["todo:read"]));
+
+        assert!(has_permission(&admin, USER_DELETE));
+        assert!(has_permission(&user, "todo:read"));
+        assert!(!has_permission(&user, USER_READ));
+    }
+}
@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::application_factory::ApplicationFactory;
+
+/// Server side state of an issued refresh token. Tokens issued by rotating an
+/// earlier token share its `family_id`, so a whole login session can be revoked at once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefreshTokenRecord {
+    pub user_id: String,
+    pub family_id: String,
+    pub issued_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Why a presented refresh token was refused. Anything else the store returns
+/// is a backend failure.
+#[derive(Debug, Error)]
+pub enum RefreshTokenError {
+    #[error("Refresh token is invalid or expired")]
+    Invalid,
+
+    #[error("Refresh token has been revoked")]
+    Revoked,
+
+    #[error("Refresh token has already been used")]
+    Reused,
+}
+
+/// Storage behind [`RefreshTokenStore`]. Entries expire after `ttl`.
+#[async_trait]
+pub trait TokenBackend: Send + Sync {
+    /// Stores the record of `token` and marks its family active.
+    async fn save(
+        &self,
+        token: &str,
+        record: &str,
+        family_id: &str,
+        user_id: &str,
+        ttl: Duration,
+    ) -> Result<()>;
+
+    async fn record(&self, token: &str) -> Result<Option<String>>;
+
+    async fn is_family_active(&self, family_id: &str) -> Result<bool>;
+
+    /// Marks `token` as rotated. Returns `false` when it already was.
+    async fn mark_used(&self, token: &str, family_id: &str, ttl: Duration) -> Result<bool>;
+
+    async fn revoke_family(&self, family_id: &str) -> Result<()>;
+}
+
+/// Keys:
+/// - `refresh_token::<token>` the [`RefreshTokenRecord`], expires with the token
+/// - `refresh_token_used::<token>` set once the token has been rotated
+/// - `refresh_family::<family_id>` present while the family is active
+pub struct RedisTokenBackend {
+    fac: Arc<ApplicationFactory>,
+}
+
+fn token_key(token: &str) -> String {
+    format!("refresh_token::{}", token)
+}
+
+fn used_key(token: &str) -> String {
+    format!("refresh_token_used::{}", token)
+}
+
+fn family_key(family_id: &str) -> String {
+    format!("refresh_family::{}", family_id)
+}
+
+impl RedisTokenBackend {
+    pub fn new(fac: Arc<ApplicationFactory>) -> Self {
+        Self { fac }
+    }
+
+    fn get_connection(&self) -> Result<redis::aio::ConnectionManager> {
+        self.fac.redis_provider.get_connection()
+    }
+}
+
+#[async_trait]
+impl TokenBackend for RedisTokenBackend {
+    async fn save(
+        &self,
+        token: &str,
+        record: &str,
+        family_id: &str,
+        user_id: &str,
+        ttl: Duration,
+    ) -> Result<()> {
+        let mut conn = self.get_connection()?;
+        let ttl = ttl.as_secs() as usize;
+
+        redis::pipe()
+            .atomic()
+            .set_ex(token_key(token), record, ttl)
+            .ignore()
+            .set_ex(family_key(family_id), user_id, ttl)
+            .ignore()
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn record(&self, token: &str) -> Result<Option<String>> {
+        let mut conn = self.get_connection()?;
+        Ok(conn.get(token_key(token)).await?)
+    }
+
+    async fn is_family_active(&self, family_id: &str) -> Result<bool> {
+        let mut conn = self.get_connection()?;
+        Ok(conn.exists(family_key(family_id)).await?)
+    }
+
+    async fn mark_used(&self, token: &str, family_id: &str, ttl: Duration) -> Result<bool> {
+        let mut conn = self.get_connection()?;
+        let first_use: Option<String> = redis::cmd("SET")
+            .arg(used_key(token))
+            .arg(family_id)
+            .arg("NX")
+            .arg("EX")
+            .arg(ttl.as_secs())
+            .query_async(&mut conn)
+            .await?;
+
+        Ok(first_use.is_some())
+    }
+
+    async fn revoke_family(&self, family_id: &str) -> Result<()> {
+        let mut conn = self.get_connection()?;
+        conn.del::<_, ()>(family_key(family_id)).await?;
+        Ok(())
+    }
+}
+
+/// Process local backend for tests and single instance development setups.
+#[derive(Default)]
+pub struct MemoryTokenBackend {
+    records: Mutex<HashMap<String, (String, Instant)>>,
+    used: Mutex<HashMap<String, Instant>>,
+    families: Mutex<HashMap<String, Instant>>,
+}
+
+impl MemoryTokenBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+fn live<V>(entries: &mut HashMap<String, V>, key: &str, expires: impl Fn(&V) -> Instant) -> bool {
+    match entries.get(key) {
+        Some(v) if expires(v) > Instant::now() => true,
+        Some(_) => {
+            entries.remove(key);
+            false
+        }
+        None => false,
+    }
+}
+
+#[async_trait]
+impl TokenBackend for MemoryTokenBackend {
+    async fn save(
+        &self,
+        token: &str,
+        record: &str,
+        family_id: &str,
+        _user_id: &str,
+        ttl: Duration,
+    ) -> Result<()> {
+        let expires = Instant::now() + ttl;
+        self.records
+            .lock()
+            .unwrap()
+            .insert(token.to_string(), (record.to_string(), expires));
+        self.families
+            .lock()
+            .unwrap()
+            .insert(family_id.to_string(), expires);
+        Ok(())
+    }
+
+    async fn record(&self, token: &str) -> Result<Option<String>> {
+        let mut records = self.records.lock().unwrap();
+        if !live(&mut records, token, |(_, expires)| *expires) {
+            return Ok(None);
+        }
+        Ok(records.get(token).map(|(record, _)| record.clone()))
+    }
+
+    async fn is_family_active(&self, family_id: &str) -> Result<bool> {
+        let mut families = self.families.lock().unwrap();
+        Ok(live(&mut families, family_id, |expires| *expires))
+    }
+
+    async fn mark_used(&self, token: &str, _family_id: &str, ttl: Duration) -> Result<bool> {
+        let mut used = self.used.lock().unwrap();
+        if live(&mut used, token, |expires| *expires) {
+            return Ok(false);
+        }
+        used.insert(token.to_string(), Instant::now() + ttl);
+        Ok(true)
+    }
+
+    async fn revoke_family(&self, family_id: &str) -> Result<()> {
+        self.families.lock().unwrap().remove(family_id);
+        Ok(())
+    }
+}
+
+/// Opaque refresh tokens, stored in redis by [`RefreshTokenStore::new`].
+pub struct RefreshTokenStore {
+    backend: Arc<dyn TokenBackend>,
+    expiry: Duration,
+}
+
+fn new_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+impl RefreshTokenStore {
+    pub fn new(fac: Arc<ApplicationFactory>, expiry_seconds: u64) -> Self {
+        Self::with_backend(Arc::new(RedisTokenBackend::new(fac)), expiry_seconds)
+    }
+
+    pub fn with_backend(backend: Arc<dyn TokenBackend>, expiry_seconds: u64) -> Self {
+        Self {
+            backend,
+            expiry: Duration::from_secs(expiry_seconds),
+        }
+    }
+
+    /// Issues a refresh token starting a new family for `user_id`.
+    pub async fn issue(&self, user_id: &str) -> Result<String> {
+        let family_id = Uuid::new_v4().to_string();
+        self.issue_in_family(user_id, &family_id).await
+    }
+
+    async fn issue_in_family(&self, user_id: &str, family_id: &str) -> Result<String> {
+        let token = new_token();
+        let record = RefreshTokenRecord {
+            user_id: user_id.to_string(),
+            family_id: family_id.to_string(),
+            issued_at: chrono::Utc::now(),
+        };
+        let record = serde_json::to_string(&record)?;
+
+        self.backend
+            .save(&token, &record, family_id, user_id, self.expiry)
+            .await?;
+
+        Ok(token)
+    }
+
+    async fn get_record(&self, token: &str) -> Result<RefreshTokenRecord> {
+        let record = self
+            .backend
+            .record(token)
+            .await?
+            .ok_or(RefreshTokenError::Invalid)?;
+
+        Ok(serde_json::from_str(&record)?)
+    }
+
+    /// Exchanges `token` for a new refresh token in the same family.
+    ///
+    /// A token can only be rotated once. Presenting it again means it was leaked,
+    /// so the whole family is revoked and every token derived from it stops working.
+    pub async fn rotate(&self, token: &str) -> Result<(RefreshTokenRecord, String)> {
+        let record = self.get_record(token).await?;
+
+        if !self.backend.is_family_active(&record.family_id).await? {
+            return Err(RefreshTokenError::Revoked.into());
+        }
+
+        let first_use = self
+            .backend
+            .mark_used(token, &record.family_id, self.expiry)
+            .await?;
+
+        if !first_use {
+            log::warn!(
+                "Refresh token reuse detected for user `{}`. Revoking family `{}`",
+                record.user_id,
+                record.family_id
+            );
+            self.revoke_family(&record.family_id).await?;
+            return Err(RefreshTokenError::Reused.into());
+        }
+
+        let next = self
+            .issue_in_family(&record.user_id, &record.family_id)
+            .await?;
+
+        Ok((record, next))
+    }
+
+    /// Revokes the family `token` belongs to.
+    pub async fn revoke(&self, token: &str) -> Result<RefreshTokenRecord> {
+        let record = self.get_record(token).await?;
+        self.revoke_family(&record.family_id).await?;
+        Ok(record)
+    }
+
+    pub async fn revoke_family(&self, family_id: &str) -> Result<()> {
+        self.backend.revoke_family(family_id).await
+    }
+}
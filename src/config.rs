@@ -0,0 +1,655 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::Result;
+use jsonwebtoken::Algorithm;
+use strum::{Display, EnumString};
+use thiserror::Error;
+
+use crate::persistence::sql_store::IdType;
+
+/// Settings that can be given in any source. Files nest them in tables split on
+/// `_` (`[jwt] algorithm = ...` is `jwt_algorithm`), environment variables use
+/// the upper case name and flags the dashed name (`--jwt-algorithm=RS256`).
+const KEYS: &[&str] = &[
+    "server_address",
+    "shutdown_timeout_seconds",
+    "log_format",
+    "log_level",
+    "log_redact_fields",
+    "session_key",
+    "token_issuer",
+    "token_expiry_days",
+    "access_token_expiry_minutes",
+    "redis_uri",
+    "mongo_uri",
+    "mongo_database",
+    "storage_backend",
+    "sql_uri",
+    "sql_id_type",
+    "jwt_algorithm",
+    "jwt_key_id",
+    "jwt_private_key_file",
+    "jwt_public_keys",
+];
+
+/// Prefix of the per collection cache TTLs, e.g. `cache_ttl_user = 60`.
+const CACHE_TTL_PREFIX: &str = "cache_ttl_";
+
+const DEFAULTS: &[(&str, &str)] = &[
+    ("server_address", "127.0.0.1:3000"),
+    ("shutdown_timeout_seconds", "30"),
+    ("log_format", "json"),
+    ("log_level", "info"),
+    ("token_expiry_days", "7"),
+    ("access_token_expiry_minutes", "15"),
+    ("storage_backend", "mongo"),
+    ("sql_id_type", "objectid"),
+    ("jwt_algorithm", "HS256"),
+];
+
+/// Config files tried when neither `--config` nor `APP_CONFIG` is given.
+const DEFAULT_FILES: &[&str] = &["config.toml", "config.yaml", "config.yml"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum StorageBackend {
+    Mongo,
+    Sql,
+    Memory,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum LogFormat {
+    /// One JSON object per line.
+    Json,
+    /// Human readable lines for local development.
+    Text,
+}
+
+/// Every problem found while loading the configuration.
+#[derive(Debug, Error)]
+#[error("Invalid configuration: {}", .0.join("; "))]
+pub struct ConfigError(pub Vec<String>);
+
+/// Typed application configuration, loaded once at startup by [`AppConfig::load`]
+/// and handed out through `ApplicationFactory::config`.
+#[derive(Clone)]
+pub struct AppConfig {
+    pub server_address: String,
+    /// How long shutdown waits for requests and websockets to finish.
+    pub shutdown_timeout_seconds: u64,
+
+    pub log_format: LogFormat,
+    /// `tracing` filter directives, e.g. `info,axum_test::websocket=debug`.
+    pub log_level: String,
+    /// Field names redacted from logs on top of the built in ones.
+    pub log_redact_fields: Vec<String>,
+
+    /// HMAC secret for `HS*` token algorithms.
+    pub session_key: String,
+    pub token_issuer: String,
+    /// Lifetime of refresh tokens.
+    pub token_expiry_days: u64,
+    pub access_token_expiry_minutes: u64,
+
+    pub redis_uri: String,
+    pub mongo_uri: String,
+    pub mongo_database: String,
+
+    pub storage_backend: StorageBackend,
+    /// SQLite URI, required for [`StorageBackend::Sql`].
+    pub sql_uri: String,
+    /// Ids given to new documents by the SQL backend.
+    pub sql_id_type: IdType,
+
+    pub jwt_algorithm: Algorithm,
+    /// `kid` of the active signing key, required for asymmetric algorithms.
+    pub jwt_key_id: Option<String>,
+    /// PEM private key, required for asymmetric algorithms.
+    pub jwt_private_key_file: Option<String>,
+    /// `(kid, path)` of PEM public keys accepted for verification.
+    pub jwt_public_keys: Vec<(String, String)>,
+
+    /// Read-through cache TTL in seconds by lower case collection name.
+    pub cache_ttl_seconds: HashMap<String, u64>,
+
+    /// `.env` file [`AppConfig::load`] read, if any. `load` runs before the log
+    /// subscriber is installed, so `main` logs it afterwards.
+    pub dotenv_file: Option<PathBuf>,
+}
+
+impl Default for AppConfig {
+    /// The built in defaults. Required settings are left empty, which suits
+    /// tests on the in memory backend.
+    fn default() -> Self {
+        let mut sources = Sources::default();
+        sources.set_defaults();
+        Self::from_sources(&sources).0
+    }
+}
+
+impl AppConfig {
+    /// Loads the configuration from, lowest precedence first:
+    /// - built in defaults
+    /// - a TOML or YAML file from `--config=<path>`, `APP_CONFIG` or `config.{toml,yaml,yml}`
+    /// - `.env`, if present
+    /// - environment variables
+    /// - `--key=value` flags in `args`
+    ///
+    /// Fails with a [`ConfigError`] listing every invalid or missing setting.
+    pub fn load(args: &[String]) -> Result<Self> {
+        let mut sources = Sources::default();
+        let flags = flags(args, &mut sources.errors);
+
+        // Taken before `.env` is applied so its values keep their own source.
+        let env: Vec<(String, String)> = std::env::vars().collect();
+        let dotenv_file = match dotenv::dotenv() {
+            Ok(path) => Some(path),
+            Err(e) if e.not_found() => None,
+            Err(e) => {
+                sources.errors.push(format!("Unable to load .env: {}", e));
+                None
+            }
+        };
+
+        sources.set_defaults();
+
+        let file = flags
+            .get("config")
+            .cloned()
+            .or_else(|| std::env::var("APP_CONFIG").ok())
+            .or_else(|| {
+                DEFAULT_FILES
+                    .iter()
+                    .find(|f| Path::new(f).exists())
+                    .map(|f| f.to_string())
+            });
+        if let Some(file) = file {
+            sources.set_file(&file);
+        }
+
+        // `.env` never overrides the environment, so anything new came from it.
+        let dotenv = std::env::vars().filter(|(name, _)| !env.iter().any(|(n, _)| n == name));
+        sources.set_env(dotenv, ".env");
+        sources.set_env(env, "env");
+        sources.set_flags(&flags);
+
+        let (mut config, mut errors) = Self::from_sources(&sources);
+        config.dotenv_file = dotenv_file;
+        if let Err(ConfigError(invalid)) = config.validate() {
+            errors.extend(invalid);
+        }
+
+        if !errors.is_empty() {
+            return Err(ConfigError(errors).into());
+        }
+
+        Ok(config)
+    }
+
+    /// Cache TTL of `collection`, `None` when it is not cached.
+    pub fn cache_ttl(&self, collection: &str) -> Option<u64> {
+        self.cache_ttl_seconds
+            .get(&collection.to_lowercase())
+            .copied()
+            .filter(|ttl| *ttl > 0)
+    }
+
+    /// Builds the config, falling back to defaults for invalid values which are
+    /// returned as errors.
+    fn from_sources(sources: &Sources) -> (Self, Vec<String>) {
+        let mut errors = sources.errors.clone();
+
+        for key in sources.values.keys() {
+            if !KEYS.contains(&key.as_str()) && !key.starts_with(CACHE_TTL_PREFIX) {
+                errors.push(format!("unknown setting `{}`", key));
+            }
+        }
+
+        let mut cache_ttl_seconds = HashMap::new();
+        for key in sources.values.keys() {
+            if let Some(collection) = key.strip_prefix(CACHE_TTL_PREFIX) {
+                if let Some(ttl) = sources.parse::<u64>(key, &mut errors) {
+                    cache_ttl_seconds.insert(collection.to_string(), ttl);
+                }
+            }
+        }
+
+        let jwt_public_keys = sources
+            .get("jwt_public_keys")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|e| !e.is_empty())
+            .filter_map(|entry| match entry.split_once('=') {
+                Some((kid, path)) => Some((kid.trim().to_string(), path.trim().to_string())),
+                None => {
+                    errors.push(format!(
+                        "jwt_public_keys entry `{}` is not `kid=path`",
+                        entry
+                    ));
+                    None
+                }
+            })
+            .collect();
+
+        let config = Self {
+            server_address: sources.get("server_address").unwrap_or_default(),
+            shutdown_timeout_seconds: sources
+                .parse("shutdown_timeout_seconds", &mut errors)
+                .unwrap_or(30),
+            log_format: sources
+                .parse("log_format", &mut errors)
+                .unwrap_or(LogFormat::Json),
+            log_level: sources.get("log_level").unwrap_or_default(),
+            log_redact_fields: sources
+                .get("log_redact_fields")
+                .unwrap_or_default()
+                .split(',')
+                .map(|f| f.trim().to_lowercase())
+                .filter(|f| !f.is_empty())
+                .collect(),
+            session_key: sources.get("session_key").unwrap_or_default(),
+            token_issuer: sources.get("token_issuer").unwrap_or_default(),
+            token_expiry_days: sources.parse("token_expiry_days", &mut errors).unwrap_or(7),
+            access_token_expiry_minutes: sources
+                .parse("access_token_expiry_minutes", &mut errors)
+                .unwrap_or(15),
+            redis_uri: sources.get("redis_uri").unwrap_or_default(),
+            mongo_uri: sources.get("mongo_uri").unwrap_or_default(),
+            mongo_database: sources.get("mongo_database").unwrap_or_default(),
+            storage_backend: sources
+                .parse("storage_backend", &mut errors)
+                .unwrap_or(StorageBackend::Mongo),
+            sql_uri: sources.get("sql_uri").unwrap_or_default(),
+            sql_id_type: sources
+                .parse("sql_id_type", &mut errors)
+                .unwrap_or(IdType::ObjectId),
+            jwt_algorithm: sources
+                .parse("jwt_algorithm", &mut errors)
+                .unwrap_or(Algorithm::HS256),
+            jwt_key_id: sources.get("jwt_key_id"),
+            jwt_private_key_file: sources.get("jwt_private_key_file"),
+            jwt_public_keys,
+            cache_ttl_seconds,
+            dotenv_file: None,
+        };
+
+        (config, errors)
+    }
+
+    /// Checks required settings and value ranges, reporting all problems at once.
+    pub fn validate(&self) -> std::result::Result<(), ConfigError> {
+        let mut errors = Vec::new();
+        let mut require = |key: &str, value: &str| {
+            if value.trim().is_empty() {
+                errors.push(format!("`{}` is required", key));
+            }
+        };
+
+        require("server_address", &self.server_address);
+        require("token_issuer", &self.token_issuer);
+        require("redis_uri", &self.redis_uri);
+
+        match self.storage_backend {
+            StorageBackend::Mongo => {
+                require("mongo_uri", &self.mongo_uri);
+                require("mongo_database", &self.mongo_database);
+            }
+            StorageBackend::Sql => require("sql_uri", &self.sql_uri),
+            StorageBackend::Memory => {}
+        }
+
+        if matches!(
+            self.jwt_algorithm,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            require("session_key", &self.session_key);
+        } else {
+            require("jwt_key_id", self.jwt_key_id.as_deref().unwrap_or(""));
+            require(
+                "jwt_private_key_file",
+                self.jwt_private_key_file.as_deref().unwrap_or(""),
+            );
+        }
+
+        if self.server_address.parse::<std::net::SocketAddr>().is_err()
+            && !self.server_address.is_empty()
+        {
+            errors.push(format!(
+                "`server_address` `{}` is not a socket address",
+                self.server_address
+            ));
+        }
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&self.log_level) {
+            errors.push(format!(
+                "`log_level` `{}` is not a valid filter: {}",
+                self.log_level, e
+            ));
+        }
+        // The sql store filters, sorts and pages in process. Fine for a local
+        // SQLite file, not for a shared database.
+        if self.storage_backend == StorageBackend::Sql
+            && !self.sql_uri.is_empty()
+            && !self.sql_uri.starts_with("sqlite:")
+        {
+            errors.push(format!(
+                "`sql_uri` `{}` is not a SQLite URI, the sql backend only supports SQLite",
+                self.sql_uri
+            ));
+        }
+        if self.token_expiry_days == 0 {
+            errors.push("`token_expiry_days` must be at least 1".to_string());
+        }
+        if self.access_token_expiry_minutes == 0 {
+            errors.push("`access_token_expiry_minutes` must be at least 1".to_string());
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError(errors))
+        }
+    }
+}
+
+/// Raw values by setting name with the source each one came from.
+#[derive(Default)]
+struct Sources {
+    values: HashMap<String, (String, String)>,
+    errors: Vec<String>,
+}
+
+impl Sources {
+    fn set(&mut self, key: &str, value: &str, source: &str) {
+        self.values
+            .insert(key.to_string(), (value.to_string(), source.to_string()));
+    }
+
+    fn get(&self, key: &str) -> Option<String> {
+        self.values
+            .get(key)
+            .map(|(v, _)| v.clone())
+            .filter(|v| !v.is_empty())
+    }
+
+    /// Parses `key`, recording an error naming its source when it is invalid.
+    fn parse<T: FromStr>(&self, key: &str, errors: &mut Vec<String>) -> Option<T> {
+        let (value, source) = self.values.get(key)?;
+        match value.parse() {
+            Ok(v) => Some(v),
+            Err(_) => {
+                errors.push(format!(
+                    "`{}` has invalid value `{}` ({})",
+                    key, value, source
+                ));
+                None
+            }
+        }
+    }
+
+    fn set_defaults(&mut self) {
+        for (key, value) in DEFAULTS {
+            self.set(key, value, "default");
+        }
+    }
+
+    fn set_env(&mut self, vars: impl IntoIterator<Item = (String, String)>, source: &str) {
+        for (name, value) in vars {
+            let key = name.to_lowercase();
+            if KEYS.contains(&key.as_str()) || key.starts_with(CACHE_TTL_PREFIX) {
+                self.set(&key, &value, &format!("{} {}", source, name));
+            }
+        }
+    }
+
+    fn set_flags(&mut self, flags: &HashMap<String, String>) {
+        for (key, value) in flags.iter().filter(|(k, _)| k.as_str() != "config") {
+            self.set(key, value, &format!("flag --{}", key.replace('_', "-")));
+        }
+    }
+
+    /// Records an error instead of failing so it is reported with the rest.
+    fn set_file(&mut self, path: &str) {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => return self.errors.push(format!("Unable to read {}: {}", path, e)),
+        };
+
+        let value: std::result::Result<serde_json::Value, String> =
+            if path.ends_with(".yaml") || path.ends_with(".yml") {
+                serde_yaml::from_str(&text).map_err(|e| format!("Invalid YAML in {}: {}", path, e))
+            } else {
+                toml::from_str(&text).map_err(|e| format!("Invalid TOML in {}: {}", path, e))
+            };
+
+        let source = format!("file {}", path);
+        match value {
+            Ok(serde_json::Value::Object(map)) => {
+                for (key, value) in map {
+                    self.set_file_value(&key, value, &source);
+                }
+            }
+            Ok(_) => self
+                .errors
+                .push(format!("{} must contain a table of settings", path)),
+            Err(e) => self.errors.push(e),
+        }
+    }
+
+    fn set_file_value(&mut self, key: &str, value: serde_json::Value, source: &str) {
+        use serde_json::Value;
+
+        let key = key.to_lowercase().replace('-', "_");
+        match value {
+            Value::Object(map) => {
+                for (k, v) in map {
+                    self.set_file_value(&format!("{}_{}", key, k), v, source);
+                }
+            }
+            Value::Array(items) => {
+                let items: Vec<String> = items.iter().map(scalar).collect();
+                self.set(&key, &items.join(","), source);
+            }
+            Value::Null => {}
+            v => self.set(&key, &scalar(&v), source),
+        }
+    }
+}
+
+fn scalar(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+/// `--key=value` flags in `args` by setting name. Other arguments are left for
+/// the caller, see [`positional_args`].
+fn flags(args: &[String], errors: &mut Vec<String>) -> HashMap<String, String> {
+    let mut flags = HashMap::new();
+    for arg in args.iter().filter(|a| a.starts_with("--")) {
+        match arg[2..].split_once('=') {
+            Some((key, value)) => {
+                flags.insert(key.replace('-', "_"), value.to_string());
+            }
+            None => errors.push(format!("Flag `{}` must be given as `--key=value`", arg)),
+        }
+    }
+    flags
+}
+
+/// `args` without the configuration flags.
+pub fn positional_args(args: &[String]) -> Vec<String> {
+    args.iter()
+        .filter(|a| !a.starts_with("--"))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    fn temp_file(name: &str, text: &str) -> String {
+        let path = std::env::temp_dir().join(format!("{}-{}", uuid::Uuid::new_v4(), name));
+        std::fs::write(&path, text).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn later_sources_take_precedence() {
+        let file = temp_file(
+            "config.toml",
+            r#"
+            log_level = "debug"
+            token_issuer = "file"
+            token_expiry_days = 2
+            mongo_database = "file"
+
+            [redis]
+            uri = "redis://file"
+            "#,
+        );
+
+        let mut sources = Sources::default();
+        let flags = flags(
+            &args(&["serve", "--token-expiry-days=5"]),
+            &mut sources.errors,
+        );
+        sources.set_defaults();
+        sources.set_file(&file);
+        sources.set_env(
+            vars(&[
+                ("TOKEN_ISSUER", "dotenv"),
+                ("TOKEN_EXPIRY_DAYS", "3"),
+                ("MONGO_DATABASE", "dotenv"),
+            ]),
+            ".env",
+        );
+        sources.set_env(
+            vars(&[("TOKEN_EXPIRY_DAYS", "4"), ("MONGO_DATABASE", "env")]),
+            "env",
+        );
+        sources.set_flags(&flags);
+        std::fs::remove_file(&file).unwrap();
+
+        let (config, errors) = AppConfig::from_sources(&sources);
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        assert_eq!(config.server_address, "127.0.0.1:3000");
+        assert_eq!(config.log_level, "debug");
+        assert_eq!(config.redis_uri, "redis://file");
+        assert_eq!(config.token_issuer, "dotenv");
+        assert_eq!(config.mongo_database, "env");
+        assert_eq!(config.token_expiry_days, 5);
+        assert_eq!(sources.values["mongo_database"].1, "env MONGO_DATABASE");
+        assert_eq!(sources.values["token_issuer"].1, ".env TOKEN_ISSUER");
+    }
+
+    #[test]
+    fn every_problem_is_reported_together() {
+        let mut sources = Sources::default();
+        let flags = flags(
+            &args(&["--storage-backend", "--token-expiry-days=soon"]),
+            &mut sources.errors,
+        );
+        sources.set_defaults();
+        sources.set_file("/nonexistent/config.toml");
+        sources.set_flags(&flags);
+
+        let (config, mut errors) = AppConfig::from_sources(&sources);
+        errors.extend(config.validate().unwrap_err().0);
+
+        let expected = [
+            "Flag `--storage-backend` must be given as `--key=value`",
+            "Unable to read /nonexistent/config.toml",
+            "`token_expiry_days` has invalid value `soon` (flag --token-expiry-days)",
+            "`token_issuer` is required",
+            "`redis_uri` is required",
+            "`mongo_uri` is required",
+            "`session_key` is required",
+        ];
+        for expected in expected {
+            assert!(
+                errors.iter().any(|e| e.starts_with(expected)),
+                "missing `{}` in {:?}",
+                expected,
+                errors
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_files_are_reported() {
+        let file = temp_file("config.toml", "log_level = ");
+        let mut sources = Sources::default();
+        sources.set_file(&file);
+        std::fs::remove_file(&file).unwrap();
+
+        assert_eq!(sources.errors.len(), 1);
+        assert!(sources.errors[0].starts_with(&format!("Invalid TOML in {}", file)));
+    }
+
+    fn complete_config() -> AppConfig {
+        AppConfig {
+            token_issuer: "issuer".to_string(),
+            redis_uri: "redis://localhost".to_string(),
+            mongo_uri: "mongodb://localhost".to_string(),
+            mongo_database: "app".to_string(),
+            session_key: "secret".to_string(),
+            ..AppConfig::default()
+        }
+    }
+
+    #[test]
+    fn a_complete_config_is_valid() {
+        let mut config = complete_config();
+        assert!(config.validate().is_ok());
+
+        config.server_address = "localhost".to_string();
+        config.access_token_expiry_minutes = 0;
+        let errors = config.validate().unwrap_err().0;
+        assert_eq!(
+            errors,
+            vec![
+                "`server_address` `localhost` is not a socket address".to_string(),
+                "`access_token_expiry_minutes` must be at least 1".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_sql_backend_requires_sqlite() {
+        let mut config = AppConfig {
+            storage_backend: StorageBackend::Sql,
+            ..complete_config()
+        };
+
+        config.sql_uri = "postgres://localhost/app".to_string();
+        assert_eq!(
+            config.validate().unwrap_err().0,
+            vec![
+                "`sql_uri` `postgres://localhost/app` is not a SQLite URI, the sql backend only supports SQLite"
+                    .to_string()
+            ]
+        );
+
+        config.sql_uri = "sqlite://app.db".to_string();
+        assert!(config.validate().is_ok());
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow as error, Result};
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+use serde::Serialize;
+
+use crate::config::StorageBackend;
+use crate::server::ServerState;
+
+/// Upper bound of a single dependency check, so a hanging dependency fails
+/// the probe instead of blocking it.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Up,
+    Down,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct CheckResult {
+    pub status: HealthStatus,
+    /// Round trip of the check, absent for in process checks.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct WebsocketStats {
+    pub connections: usize,
+    pub rooms: usize,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub checks: BTreeMap<&'static str, CheckResult>,
+    pub websockets: WebsocketStats,
+}
+
+pub fn health_routes() -> Router<Arc<ServerState>> {
+    Router::new()
+        .route("/live", get(live))
+        .route("/ready", get(ready))
+}
+
+/// `GET /health/live`, up as long as the process serves requests.
+pub async fn live() -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "status": HealthStatus::Up }))
+}
+
+/// `GET /health/ready`, `200` when the storage backend, redis and the pubsub
+/// adapter are up and `503` otherwise.
+pub async fn ready(State(state): State<Arc<ServerState>>) -> (StatusCode, Json<HealthReport>) {
+    let report = readiness(&state).await;
+    let code = match report.status {
+        HealthStatus::Up => StatusCode::OK,
+        HealthStatus::Down => StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    (code, Json(report))
+}
+
+pub async fn readiness(state: &ServerState) -> HealthReport {
+    let mut checks = BTreeMap::new();
+
+    // Providers are cloned out so no lock is held across the pings.
+    let providers = state
+        .appliction_factory
+        .lock()
+        .map(|fac| {
+            (
+                fac.config.storage_backend,
+                fac.mongo_provider.clone(),
+                fac.sql_provider.clone(),
+                fac.redis_provider.clone(),
+            )
+        })
+        .map_err(|e| error!("Factory lock error: {}", e));
+
+    match providers {
+        Ok((backend, mongo, sql, redis)) => {
+            match backend {
+                StorageBackend::Mongo => {
+                    checks.insert("mongo", timed(mongo.ping()).await);
+                }
+                StorageBackend::Sql => {
+                    checks.insert("sql", timed(sql.ping()).await);
+                }
+                StorageBackend::Memory => {}
+            }
+
+            checks.insert("redis", timed(redis.ping()).await);
+        }
+        Err(e) => {
+            checks.insert("factory", CheckResult::down(e));
+        }
+    }
+
+    let pubsub = match state.pubsub_adapter.lock() {
+        Ok(adapter) if adapter.is_stopped() => {
+            CheckResult::down(error!("Pubsub adapter is stopped"))
+        }
+        Ok(_) => CheckResult::up(None),
+        Err(e) => CheckResult::down(error!("Adapter lock error: {}", e)),
+    };
+    checks.insert("pubsub", pubsub);
+
+    let websockets = state
+        .websocke_server
+        .lock()
+        .map(|ws| WebsocketStats {
+            connections: ws.client_count(),
+            rooms: ws.room_count(),
+        })
+        .unwrap_or(WebsocketStats {
+            connections: 0,
+            rooms: 0,
+        });
+
+    let status = if checks.values().all(|c| c.status == HealthStatus::Up) {
+        HealthStatus::Up
+    } else {
+        HealthStatus::Down
+    };
+
+    HealthReport {
+        status,
+        checks,
+        websockets,
+    }
+}
+
+impl CheckResult {
+    fn up(latency_ms: Option<f64>) -> Self {
+        Self {
+            status: HealthStatus::Up,
+            latency_ms,
+            error: None,
+        }
+    }
+
+    fn down(e: anyhow::Error) -> Self {
+        Self {
+            status: HealthStatus::Down,
+            latency_ms: None,
+            error: Some(e.to_string()),
+        }
+    }
+}
+
+/// Runs `check` within [`CHECK_TIMEOUT`] and records how long it took.
+async fn timed<F: Future<Output = Result<()>>>(check: F) -> CheckResult {
+    let start = Instant::now();
+    let result = tokio::time::timeout(CHECK_TIMEOUT, check).await;
+    let latency_ms = Some(start.elapsed().as_secs_f64() * 1000.0);
+
+    match result {
+        Ok(Ok(())) => CheckResult::up(latency_ms),
+        Ok(Err(e)) => CheckResult {
+            latency_ms,
+            ..CheckResult::down(e)
+        },
+        Err(_) => CheckResult {
+            latency_ms,
+            ..CheckResult::down(error!("Timed out after {:?}", CHECK_TIMEOUT))
+        },
+    }
+}
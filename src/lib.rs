@@ -1,10 +1,14 @@
 pub mod app;
 pub mod application_factory;
 pub mod auth;
-pub mod secrets;
+pub mod config;
+pub mod health;
+pub mod metrics;
 pub mod server;
 pub mod server_errors;
+pub mod telemetry;
 pub mod utils;
+pub mod validation;
 
 pub mod websocket;
 
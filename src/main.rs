@@ -3,28 +3,46 @@ use std::sync::{Arc, Mutex};
 //#![allow(unused_imports)]
 use anyhow::Result;
 
+use axum_test::app::migration::MigrationRunner;
+use axum_test::app::migrations;
 use axum_test::application_factory::ApplicationFactory;
 use axum_test::server;
+use axum_test::telemetry;
 
-use axum_test::secrets;
-use dotenv::dotenv;
+use axum_test::config::{self, AppConfig, StorageBackend};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    println!("Hello, world!");
-
-    let rust_log = std::env::var("RUST_LOG").unwrap_or_else(|_| {
-        std::env::set_var("RUST_LOG", "info");
-        "info".to_string()
-    });
-    println!("Using log level: {}", rust_log);
-    env_logger::init();
-
-    dotenv().expect(".dot env file unable to load");
-
-    let address = secrets::SERVER_ADDRESS.to_string();
-
-    let fac = ApplicationFactory::new().await?;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let config = Arc::new(AppConfig::load(&args)?);
+
+    telemetry::init_subscriber(&config)?;
+    match &config.dotenv_file {
+        Some(path) => log::info!("Loaded {}", path.display()),
+        None => log::info!("No .env file found"),
+    }
+    log::info!(
+        "Using log level: {}, format: {}",
+        config.log_level,
+        config.log_format
+    );
+
+    let args = config::positional_args(&args);
+    if args.first().map(|a| a.as_str()) == Some("migrate") {
+        let fac = Arc::new(ApplicationFactory::new(config).await?);
+        let runner = MigrationRunner::new(fac, migrations::migrations())?;
+        return runner.run_command(&args[1..]).await;
+    }
+
+    let address = config.server_address.clone();
+
+    let fac = Arc::new(ApplicationFactory::new(config.clone()).await?);
+    if config.storage_backend == StorageBackend::Mongo {
+        MigrationRunner::new(fac.clone(), migrations::migrations())?
+            .ensure_applied()
+            .await?;
+    }
+    let fac = Arc::into_inner(fac).expect("factory is not shared yet");
 
     let app_factory = Arc::new(Mutex::new(fac));
 
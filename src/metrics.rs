@@ -0,0 +1,172 @@
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use axum::{
+    extract::{MatchedPath, State},
+    http::{header, Request},
+    response::{IntoResponse, Response},
+};
+use futures::future::BoxFuture;
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use tower::{Layer, Service};
+
+use crate::server::ServerState;
+use crate::server_errors::ServerError;
+
+pub static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+pub static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route and status"),
+            &["method", "route", "status"],
+        )
+        .expect("http_requests_total metric"),
+    )
+});
+
+pub static HTTP_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register(
+        HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "HTTP request latency by route and status",
+            ),
+            &["method", "route", "status"],
+        )
+        .expect("http_request_duration_seconds metric"),
+    )
+});
+
+pub static WEBSOCKET_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register(
+        IntGauge::new("websocket_connections", "Connected websocket clients")
+            .expect("websocket_connections metric"),
+    )
+});
+
+pub static WEBSOCKET_ROOMS: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new("websocket_rooms", "Websocket rooms").expect("websocket_rooms metric"))
+});
+
+/// `direction` is `published` for room messages sent to redis and `received`
+/// for messages the pubsub adapter reads back.
+pub static PUBSUB_MESSAGES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("pubsub_messages_total", "Redis pubsub room messages"),
+            &["direction"],
+        )
+        .expect("pubsub_messages_total metric"),
+    )
+});
+
+pub static DAO_OPERATION_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register(
+        HistogramVec::new(
+            HistogramOpts::new(
+                "dao_operation_duration_seconds",
+                "Storage operation latency by collection",
+            ),
+            &["collection", "operation", "outcome"],
+        )
+        .expect("dao_operation_duration_seconds metric"),
+    )
+});
+
+fn register<C: prometheus::core::Collector + Clone + 'static>(collector: C) -> C {
+    REGISTRY
+        .register(Box::new(collector.clone()))
+        .expect("metric registered twice");
+    collector
+}
+
+/// `GET /metrics` in the Prometheus text format.
+pub async fn metrics(State(state): State<Arc<ServerState>>) -> Result<Response, ServerError> {
+    if let Ok(ws) = state.websocke_server.lock() {
+        WEBSOCKET_CONNECTIONS.set(ws.client_count() as i64);
+        WEBSOCKET_ROOMS.set(ws.room_count() as i64);
+    }
+
+    // Touch the lazies so every family is listed before its first sample.
+    Lazy::force(&HTTP_REQUESTS);
+    Lazy::force(&HTTP_REQUEST_DURATION);
+    Lazy::force(&PUBSUB_MESSAGES);
+    Lazy::force(&DAO_OPERATION_DURATION);
+
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    encoder
+        .encode(&REGISTRY.gather(), &mut body)
+        .map_err(|e| ServerError::Internal(e.to_string()))?;
+
+    Ok((
+        [(header::CONTENT_TYPE, encoder.format_type().to_string())],
+        body,
+    )
+        .into_response())
+}
+
+/// Records [`HTTP_REQUESTS`] and [`HTTP_REQUEST_DURATION`]. Requests that match
+/// no route are labeled `unmatched` to keep the label set bounded.
+#[derive(Debug, Clone, Default)]
+pub struct HttpMetricsLayer;
+
+impl<S> Layer<S> for HttpMetricsLayer {
+    type Service = HttpMetrics<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HttpMetrics { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpMetrics<S> {
+    inner: S,
+}
+
+impl<S, B> Service<Request<B>> for HttpMetrics<S>
+where
+    S: Service<Request<B>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // Take the service that was driven to readiness and leave a clone behind.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let method = req.method().to_string();
+        let route = req
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|p| p.as_str().to_string())
+            .unwrap_or_else(|| "unmatched".to_string());
+        let start = Instant::now();
+
+        Box::pin(async move {
+            let response = inner.call(req).await?;
+
+            let status = response.status().as_u16().to_string();
+            let labels = [method.as_str(), route.as_str(), status.as_str()];
+            HTTP_REQUESTS.with_label_values(&labels).inc();
+            HTTP_REQUEST_DURATION
+                .with_label_values(&labels)
+                .observe(start.elapsed().as_secs_f64());
+
+            Ok(response)
+        })
+    }
+}
@@ -1,3 +1,9 @@
+pub mod document_filter;
+pub mod document_store;
+pub mod memory_store;
+pub mod metered_store;
 pub mod mongo_persistence;
+pub mod sql_provider;
+pub mod sql_store;
 
 pub mod redis_provider;
@@ -0,0 +1,409 @@
+use std::cmp::Ordering;
+
+use anyhow::{anyhow as error, Result};
+use mongodb::bson::{Bson, Document};
+use mongodb::options::FindOptions;
+use regex::RegexBuilder;
+
+use crate::app::index::IndexSpec;
+
+/// Documents matching `filter`, ordered and windowed by the `sort`, `skip` and
+/// `limit` of `options`.
+pub fn select<'a>(
+    docs: impl Iterator<Item = &'a Document>,
+    filter: &Document,
+    options: &FindOptions,
+) -> Result<Vec<Document>> {
+    let mut found = Vec::new();
+    for doc in docs {
+        if matches(doc, filter)? {
+            found.push(doc.clone());
+        }
+    }
+
+    if let Some(sort) = &options.sort {
+        found.sort_by(|a, b| compare_by_sort(a, b, sort));
+    }
+
+    let skip = options.skip.unwrap_or(0) as usize;
+    let limit = match options.limit {
+        Some(l) if l != 0 => l.unsigned_abs() as usize,
+        _ => usize::MAX,
+    };
+
+    Ok(found.into_iter().skip(skip).take(limit).collect())
+}
+
+/// The `_id` a filter pins down through plain equality, directly or inside `$and`.
+/// Lets stores look a document up by key before evaluating the full filter.
+pub fn id_hint(filter: &Document) -> Option<&Bson> {
+    if let Some(id) = filter.get("_id") {
+        if is_operator_doc(id).is_none() {
+            return Some(id);
+        }
+    }
+
+    filter
+        .get_array("$and")
+        .ok()?
+        .iter()
+        .filter_map(|f| f.as_document())
+        .find_map(id_hint)
+}
+
+pub fn index_applies(spec: &IndexSpec, doc: &Document) -> bool {
+    if spec.sparse && !spec.keys.keys().any(|k| lookup(doc, k).is_some()) {
+        return false;
+    }
+
+    match &spec.partial_filter {
+        Some(filter) => matches(doc, filter).unwrap_or(false),
+        None => true,
+    }
+}
+
+/// Value at a dotted `path`, `None` when any part is missing.
+pub fn lookup<'a>(doc: &'a Document, path: &str) -> Option<&'a Bson> {
+    let mut parts = path.split('.');
+    let mut current = doc.get(parts.next()?)?;
+    for part in parts {
+        current = current.as_document()?.get(part)?;
+    }
+    Some(current)
+}
+
+pub fn matches(doc: &Document, filter: &Document) -> Result<bool> {
+    for (key, expected) in filter {
+        let ok = match key.as_str() {
+            "$and" => {
+                let mut all = true;
+                for f in sub_filters(key, expected)? {
+                    all = all && matches(doc, f)?;
+                }
+                all
+            }
+            "$or" => {
+                let mut any = false;
+                for f in sub_filters(key, expected)? {
+                    any = any || matches(doc, f)?;
+                }
+                any
+            }
+            "$nor" => {
+                let mut any = false;
+                for f in sub_filters(key, expected)? {
+                    any = any || matches(doc, f)?;
+                }
+                !any
+            }
+            op if op.starts_with('$') => {
+                return Err(error!("Unsupported query operator `{}`", op));
+            }
+            field => matches_field(lookup(doc, field), expected)?,
+        };
+
+        if !ok {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+fn sub_filters<'a>(op: &str, value: &'a Bson) -> Result<Vec<&'a Document>> {
+    let arr = value
+        .as_array()
+        .ok_or(error!("`{}` expects an array of filters", op))?;
+
+    arr.iter()
+        .map(|f| {
+            f.as_document()
+                .ok_or(error!("`{}` expects an array of filters", op))
+        })
+        .collect()
+}
+
+fn is_operator_doc(value: &Bson) -> Option<&Document> {
+    value
+        .as_document()
+        .filter(|d| d.keys().next().is_some_and(|k| k.starts_with('$')))
+}
+
+fn matches_field(value: Option<&Bson>, expected: &Bson) -> Result<bool> {
+    let ops = match is_operator_doc(expected) {
+        Some(ops) => ops,
+        None => return Ok(matches_value(value, expected)),
+    };
+
+    for (op, arg) in ops {
+        let ok = match op.as_str() {
+            "$eq" => matches_value(value, arg),
+            "$ne" => !matches_value(value, arg),
+            "$in" => in_array(value, arg, op)?,
+            "$nin" => !in_array(value, arg, op)?,
+            "$gt" => compares(value, arg, |o| o == Ordering::Greater),
+            "$gte" => compares(value, arg, |o| o != Ordering::Less),
+            "$lt" => compares(value, arg, |o| o == Ordering::Less),
+            "$lte" => compares(value, arg, |o| o != Ordering::Greater),
+            "$exists" => value.is_some() == truthy(arg),
+            "$type" => has_type(value, arg)?,
+            "$regex" => {
+                let options = ops.get_str("$options").unwrap_or("");
+                let pattern = match arg {
+                    Bson::String(s) => s.as_str(),
+                    Bson::RegularExpression(r) => r.pattern.as_str(),
+                    _ => return Err(error!("`$regex` expects a string")),
+                };
+                regex_matches(value, pattern, options)?
+            }
+            "$options" => true,
+            _ => return Err(error!("Unsupported query operator `{}`", op)),
+        };
+
+        if !ok {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Equality with Mongo semantics: null also matches a missing field and a
+/// scalar matches an array containing it.
+fn matches_value(value: Option<&Bson>, expected: &Bson) -> bool {
+    if let Bson::RegularExpression(r) = expected {
+        return regex_matches(value, &r.pattern, &r.options).unwrap_or(false);
+    }
+
+    match value {
+        None => *expected == Bson::Null,
+        Some(Bson::Array(items)) if !matches!(expected, Bson::Array(_)) => {
+            items.iter().any(|v| bson_eq(v, expected))
+        }
+        Some(v) => bson_eq(v, expected),
+    }
+}
+
+fn in_array(value: Option<&Bson>, arg: &Bson, op: &str) -> Result<bool> {
+    let candidates = arg.as_array().ok_or(error!("`{}` expects an array", op))?;
+
+    Ok(candidates.iter().any(|c| matches_value(value, c)))
+}
+
+/// Range comparisons only match values of the same type class, like Mongo.
+fn compares(value: Option<&Bson>, arg: &Bson, accept: impl Fn(Ordering) -> bool) -> bool {
+    let check = |v: &Bson| {
+        type_rank(Some(v)) == type_rank(Some(arg)) && accept(bson_cmp(Some(v), Some(arg)))
+    };
+
+    match value {
+        Some(Bson::Array(items)) => items.iter().any(check),
+        Some(v) => check(v),
+        None => false,
+    }
+}
+
+/// `$type` with a type alias or an array of them.
+fn has_type(value: Option<&Bson>, arg: &Bson) -> Result<bool> {
+    let Some(value) = value else {
+        return Ok(false);
+    };
+
+    let aliases = match arg {
+        Bson::Array(items) => items.iter().collect(),
+        alias => vec![alias],
+    };
+
+    for alias in aliases {
+        let alias = alias
+            .as_str()
+            .ok_or(error!("`$type` expects a type alias like `string`"))?;
+        let is = match alias {
+            "double" => matches!(value, Bson::Double(_)),
+            "string" => matches!(value, Bson::String(_)),
+            "object" => matches!(value, Bson::Document(_)),
+            "array" => matches!(value, Bson::Array(_)),
+            "binData" => matches!(value, Bson::Binary(_)),
+            "objectId" => matches!(value, Bson::ObjectId(_)),
+            "bool" => matches!(value, Bson::Boolean(_)),
+            "date" => matches!(value, Bson::DateTime(_)),
+            "null" => matches!(value, Bson::Null),
+            "regex" => matches!(value, Bson::RegularExpression(_)),
+            "int" => matches!(value, Bson::Int32(_)),
+            "timestamp" => matches!(value, Bson::Timestamp(_)),
+            "long" => matches!(value, Bson::Int64(_)),
+            "decimal" => matches!(value, Bson::Decimal128(_)),
+            "number" => type_rank(Some(value)) == 2,
+            other => return Err(error!("Unsupported `$type` alias `{}`", other)),
+        };
+
+        if is {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+fn regex_matches(value: Option<&Bson>, pattern: &str, options: &str) -> Result<bool> {
+    let re = RegexBuilder::new(pattern)
+        .case_insensitive(options.contains('i'))
+        .multi_line(options.contains('m'))
+        .dot_matches_new_line(options.contains('s'))
+        .ignore_whitespace(options.contains('x'))
+        .build()?;
+
+    let is_match = |v: &Bson| v.as_str().is_some_and(|s| re.is_match(s));
+
+    Ok(match value {
+        Some(Bson::Array(items)) => items.iter().any(is_match),
+        Some(v) => is_match(v),
+        None => false,
+    })
+}
+
+fn truthy(value: &Bson) -> bool {
+    match value {
+        Bson::Boolean(b) => *b,
+        Bson::Null => false,
+        v => match as_f64(v) {
+            Some(n) => n != 0.0,
+            None => true,
+        },
+    }
+}
+
+fn as_f64(value: &Bson) -> Option<f64> {
+    match value {
+        Bson::Int32(v) => Some(*v as f64),
+        Bson::Int64(v) => Some(*v as f64),
+        Bson::Double(v) => Some(*v),
+        _ => None,
+    }
+}
+
+fn bson_eq(a: &Bson, b: &Bson) -> bool {
+    bson_cmp(Some(a), Some(b)) == Ordering::Equal
+}
+
+/// Mongo's cross type sort order, missing fields sorting with null.
+fn type_rank(value: Option<&Bson>) -> u8 {
+    match value {
+        None | Some(Bson::Null) | Some(Bson::Undefined) => 1,
+        Some(Bson::Int32(_)) | Some(Bson::Int64(_)) | Some(Bson::Double(_)) => 2,
+        Some(Bson::Decimal128(_)) => 2,
+        Some(Bson::String(_)) | Some(Bson::Symbol(_)) => 3,
+        Some(Bson::Document(_)) => 4,
+        Some(Bson::Array(_)) => 5,
+        Some(Bson::Binary(_)) => 6,
+        Some(Bson::ObjectId(_)) => 7,
+        Some(Bson::Boolean(_)) => 8,
+        Some(Bson::DateTime(_)) => 9,
+        Some(Bson::Timestamp(_)) => 10,
+        Some(Bson::RegularExpression(_)) => 11,
+        Some(_) => 12,
+    }
+}
+
+pub fn bson_cmp(a: Option<&Bson>, b: Option<&Bson>) -> Ordering {
+    let rank = type_rank(a).cmp(&type_rank(b));
+    if rank != Ordering::Equal {
+        return rank;
+    }
+
+    match (a, b) {
+        (Some(Bson::String(x)), Some(Bson::String(y))) => x.cmp(y),
+        (Some(Bson::ObjectId(x)), Some(Bson::ObjectId(y))) => x.bytes().cmp(&y.bytes()),
+        (Some(Bson::Boolean(x)), Some(Bson::Boolean(y))) => x.cmp(y),
+        (Some(Bson::DateTime(x)), Some(Bson::DateTime(y))) => x.cmp(y),
+        (Some(Bson::Timestamp(x)), Some(Bson::Timestamp(y))) => {
+            (x.time, x.increment).cmp(&(y.time, y.increment))
+        }
+        (Some(Bson::Array(x)), Some(Bson::Array(y))) => {
+            for (l, r) in x.iter().zip(y.iter()) {
+                let o = bson_cmp(Some(l), Some(r));
+                if o != Ordering::Equal {
+                    return o;
+                }
+            }
+            x.len().cmp(&y.len())
+        }
+        (Some(Bson::Document(x)), Some(Bson::Document(y))) => {
+            for ((lk, lv), (rk, rv)) in x.iter().zip(y.iter()) {
+                let o = lk.cmp(rk).then(bson_cmp(Some(lv), Some(rv)));
+                if o != Ordering::Equal {
+                    return o;
+                }
+            }
+            x.len().cmp(&y.len())
+        }
+        (Some(x), Some(y)) => match (as_f64(x), as_f64(y)) {
+            (Some(l), Some(r)) => l.partial_cmp(&r).unwrap_or(Ordering::Equal),
+            _ => {
+                if x == y {
+                    Ordering::Equal
+                } else {
+                    format!("{:?}", x).cmp(&format!("{:?}", y))
+                }
+            }
+        },
+        _ => Ordering::Equal,
+    }
+}
+
+pub fn compare_by_sort(a: &Document, b: &Document, sort: &Document) -> Ordering {
+    for (field, direction) in sort {
+        let o = bson_cmp(lookup(a, field), lookup(b, field));
+        let o = if as_f64(direction).is_some_and(|d| d < 0.0) {
+            o.reverse()
+        } else {
+            o
+        };
+
+        if o != Ordering::Equal {
+            return o;
+        }
+    }
+
+    Ordering::Equal
+}
+
+pub fn apply_update(doc: &mut Document, update: &Document) -> Result<()> {
+    for (op, fields) in update {
+        let fields = fields
+            .as_document()
+            .ok_or(error!("`{}` expects a document", op))?;
+
+        for (field, value) in fields {
+            if field.contains('.') {
+                return Err(error!("Dotted update paths are not supported: `{}`", field));
+            }
+
+            match op.as_str() {
+                "$set" => {
+                    doc.insert(field, value.clone());
+                }
+                "$unset" => {
+                    doc.remove(field);
+                }
+                "$inc" => {
+                    let current = doc.get(field).cloned().unwrap_or(Bson::Int32(0));
+                    let sum = match (&current, value) {
+                        (Bson::Int32(a), Bson::Int32(b)) => Bson::Int32(a + b),
+                        (Bson::Int32(a), Bson::Int64(b)) => Bson::Int64(*a as i64 + b),
+                        (Bson::Int64(a), Bson::Int32(b)) => Bson::Int64(a + *b as i64),
+                        (Bson::Int64(a), Bson::Int64(b)) => Bson::Int64(a + b),
+                        (a, b) => match (as_f64(a), as_f64(b)) {
+                            (Some(a), Some(b)) => Bson::Double(a + b),
+                            _ => return Err(error!("Cannot `$inc` non numeric field `{}`", field)),
+                        },
+                    };
+                    doc.insert(field, sum);
+                }
+                _ => return Err(error!("Unsupported update operator `{}`", op)),
+            }
+        }
+    }
+
+    Ok(())
+}
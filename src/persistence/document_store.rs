@@ -0,0 +1,358 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::StreamExt;
+use mongodb::bson::{oid::ObjectId, Bson, Document};
+use mongodb::error::{ErrorKind, WriteFailure};
+use mongodb::options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument};
+use mongodb::ClientSession;
+
+use crate::app::dao::DaoError;
+use crate::app::index::{sync_indexes, IndexSpec};
+use crate::persistence::mongo_persistence::MongoProvider;
+
+/// Storage operations `DaoObj` is built on. Documents are plain BSON using Mongo
+/// filter, sort and update syntax, so every backend behaves like Mongo for the
+/// subset `DaoObj` uses.
+#[async_trait]
+pub trait DocumentStore: Send + Sync {
+    /// Id given to inserted documents that carry none.
+    fn new_id(&self) -> Bson {
+        Bson::ObjectId(ObjectId::new())
+    }
+
+    /// Inserts `doc`, assigning an `_id` when it has none. Returns the `_id`.
+    async fn insert_one(&self, collection: &str, doc: Document) -> Result<Bson>;
+
+    async fn find_one(&self, collection: &str, filter: Document) -> Result<Option<Document>>;
+
+    /// Uses `sort`, `skip` and `limit` from `options`.
+    async fn find(
+        &self,
+        collection: &str,
+        filter: Document,
+        options: FindOptions,
+    ) -> Result<Vec<Document>>;
+
+    async fn count(&self, collection: &str, filter: Document) -> Result<u64>;
+
+    /// Applies `update` to the first match and returns the updated document.
+    async fn find_one_and_update(
+        &self,
+        collection: &str,
+        filter: Document,
+        update: Document,
+    ) -> Result<Option<Document>>;
+
+    /// Returns the number of matched documents.
+    async fn update_one(&self, collection: &str, filter: Document, update: Document)
+        -> Result<u64>;
+
+    /// Returns the number of deleted documents.
+    async fn delete_one(&self, collection: &str, filter: Document) -> Result<u64>;
+
+    /// Creates or rebuilds the indexes in `specs`. Other indexes are only
+    /// dropped with `drop_undeclared`.
+    async fn sync_indexes(
+        &self,
+        collection: &str,
+        specs: &[IndexSpec],
+        drop_undeclared: bool,
+    ) -> Result<()>;
+
+    /// Session for the `*_with_session` operations below, which run inside its
+    /// transaction. Only Mongo supports them, other backends return an error.
+    async fn start_session(&self) -> Result<ClientSession> {
+        Err(sessions_unsupported())
+    }
+
+    async fn insert_one_with_session(
+        &self,
+        _session: &mut ClientSession,
+        _collection: &str,
+        _doc: Document,
+    ) -> Result<Bson> {
+        Err(sessions_unsupported())
+    }
+
+    async fn find_with_session(
+        &self,
+        _session: &mut ClientSession,
+        _collection: &str,
+        _filter: Document,
+        _options: FindOptions,
+    ) -> Result<Vec<Document>> {
+        Err(sessions_unsupported())
+    }
+
+    async fn count_with_session(
+        &self,
+        _session: &mut ClientSession,
+        _collection: &str,
+        _filter: Document,
+    ) -> Result<u64> {
+        Err(sessions_unsupported())
+    }
+
+    async fn find_one_and_update_with_session(
+        &self,
+        _session: &mut ClientSession,
+        _collection: &str,
+        _filter: Document,
+        _update: Document,
+    ) -> Result<Option<Document>> {
+        Err(sessions_unsupported())
+    }
+
+    async fn update_one_with_session(
+        &self,
+        _session: &mut ClientSession,
+        _collection: &str,
+        _filter: Document,
+        _update: Document,
+    ) -> Result<u64> {
+        Err(sessions_unsupported())
+    }
+
+    async fn delete_one_with_session(
+        &self,
+        _session: &mut ClientSession,
+        _collection: &str,
+        _filter: Document,
+    ) -> Result<u64> {
+        Err(sessions_unsupported())
+    }
+}
+
+fn sessions_unsupported() -> anyhow::Error {
+    anyhow::anyhow!("Transactions are only supported by the mongo storage backend")
+}
+
+const DUPLICATE_KEY: i32 = 11000;
+
+/// Server message of a Mongo duplicate key error, `None` for other errors.
+pub fn duplicate_key_message(e: &mongodb::error::Error) -> Option<String> {
+    match &*e.kind {
+        ErrorKind::Write(WriteFailure::WriteError(we)) if we.code == DUPLICATE_KEY => {
+            Some(we.message.clone())
+        }
+        ErrorKind::Command(c) if c.code == DUPLICATE_KEY => Some(c.message.clone()),
+        _ => None,
+    }
+}
+
+/// Turns Mongo duplicate key errors into [`DaoError::DuplicateKey`].
+pub fn map_mongo_error(e: mongodb::error::Error) -> anyhow::Error {
+    match duplicate_key_message(&e) {
+        Some(message) => DaoError::DuplicateKey(message).into(),
+        None => e.into(),
+    }
+}
+
+pub struct MongoDocumentStore {
+    provider: MongoProvider,
+}
+
+impl MongoDocumentStore {
+    pub fn new(provider: MongoProvider) -> Self {
+        Self { provider }
+    }
+
+    fn collection(&self, name: &str) -> Result<mongodb::Collection<Document>> {
+        Ok(self.provider.get_database()?.collection::<Document>(name))
+    }
+}
+
+#[async_trait]
+impl DocumentStore for MongoDocumentStore {
+    async fn insert_one(&self, collection: &str, mut doc: Document) -> Result<Bson> {
+        if !doc.contains_key("_id") {
+            doc.insert("_id", self.new_id());
+        }
+
+        let res = self
+            .collection(collection)?
+            .insert_one(doc, None)
+            .await
+            .map_err(map_mongo_error)?;
+
+        Ok(res.inserted_id)
+    }
+
+    async fn find_one(&self, collection: &str, filter: Document) -> Result<Option<Document>> {
+        Ok(self.collection(collection)?.find_one(filter, None).await?)
+    }
+
+    async fn find(
+        &self,
+        collection: &str,
+        filter: Document,
+        options: FindOptions,
+    ) -> Result<Vec<Document>> {
+        let mut cursor = self.collection(collection)?.find(filter, options).await?;
+
+        let mut data = Vec::new();
+        while let Some(doc) = cursor.next().await {
+            data.push(doc?);
+        }
+
+        Ok(data)
+    }
+
+    async fn count(&self, collection: &str, filter: Document) -> Result<u64> {
+        Ok(self
+            .collection(collection)?
+            .count_documents(filter, None)
+            .await?)
+    }
+
+    async fn find_one_and_update(
+        &self,
+        collection: &str,
+        filter: Document,
+        update: Document,
+    ) -> Result<Option<Document>> {
+        let opt = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+
+        self.collection(collection)?
+            .find_one_and_update(filter, update, opt)
+            .await
+            .map_err(map_mongo_error)
+    }
+
+    async fn update_one(
+        &self,
+        collection: &str,
+        filter: Document,
+        update: Document,
+    ) -> Result<u64> {
+        let res = self
+            .collection(collection)?
+            .update_one(filter, update, None)
+            .await
+            .map_err(map_mongo_error)?;
+
+        Ok(res.matched_count)
+    }
+
+    async fn delete_one(&self, collection: &str, filter: Document) -> Result<u64> {
+        let res = self
+            .collection(collection)?
+            .delete_one(filter, None)
+            .await?;
+
+        Ok(res.deleted_count)
+    }
+
+    async fn sync_indexes(
+        &self,
+        collection: &str,
+        specs: &[IndexSpec],
+        drop_undeclared: bool,
+    ) -> Result<()> {
+        sync_indexes(&self.collection(collection)?, specs, drop_undeclared).await
+    }
+
+    async fn start_session(&self) -> Result<ClientSession> {
+        Ok(self.provider.get_client()?.start_session(None).await?)
+    }
+
+    async fn insert_one_with_session(
+        &self,
+        session: &mut ClientSession,
+        collection: &str,
+        mut doc: Document,
+    ) -> Result<Bson> {
+        if !doc.contains_key("_id") {
+            doc.insert("_id", self.new_id());
+        }
+
+        let res = self
+            .collection(collection)?
+            .insert_one_with_session(doc, None, session)
+            .await
+            .map_err(map_mongo_error)?;
+
+        Ok(res.inserted_id)
+    }
+
+    async fn find_with_session(
+        &self,
+        session: &mut ClientSession,
+        collection: &str,
+        filter: Document,
+        options: FindOptions,
+    ) -> Result<Vec<Document>> {
+        let mut cursor = self
+            .collection(collection)?
+            .find_with_session(filter, options, session)
+            .await?;
+
+        let mut data = Vec::new();
+        while let Some(doc) = cursor.next(session).await {
+            data.push(doc?);
+        }
+
+        Ok(data)
+    }
+
+    async fn count_with_session(
+        &self,
+        session: &mut ClientSession,
+        collection: &str,
+        filter: Document,
+    ) -> Result<u64> {
+        Ok(self
+            .collection(collection)?
+            .count_documents_with_session(filter, None, session)
+            .await?)
+    }
+
+    async fn find_one_and_update_with_session(
+        &self,
+        session: &mut ClientSession,
+        collection: &str,
+        filter: Document,
+        update: Document,
+    ) -> Result<Option<Document>> {
+        let opt = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+
+        self.collection(collection)?
+            .find_one_and_update_with_session(filter, update, opt, session)
+            .await
+            .map_err(map_mongo_error)
+    }
+
+    async fn update_one_with_session(
+        &self,
+        session: &mut ClientSession,
+        collection: &str,
+        filter: Document,
+        update: Document,
+    ) -> Result<u64> {
+        let res = self
+            .collection(collection)?
+            .update_one_with_session(filter, update, None, session)
+            .await
+            .map_err(map_mongo_error)?;
+
+        Ok(res.matched_count)
+    }
+
+    async fn delete_one_with_session(
+        &self,
+        session: &mut ClientSession,
+        collection: &str,
+        filter: Document,
+    ) -> Result<u64> {
+        let res = self
+            .collection(collection)?
+            .delete_one_with_session(filter, None, session)
+            .await?;
+
+        Ok(res.deleted_count)
+    }
+}
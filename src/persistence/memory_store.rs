@@ -0,0 +1,223 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use anyhow::{anyhow as error, Result};
+use async_trait::async_trait;
+use mongodb::bson::{Bson, Document};
+use mongodb::options::FindOptions;
+
+use crate::app::dao::DaoError;
+use crate::app::index::{reconcile, IndexSpec};
+use crate::persistence::document_filter::{
+    apply_update, bson_cmp, index_applies, lookup, matches, select,
+};
+use crate::persistence::document_store::DocumentStore;
+
+#[derive(Default)]
+struct MemoryCollection {
+    docs: Vec<Document>,
+    indexes: Vec<IndexSpec>,
+}
+
+/// [`DocumentStore`] keeping every collection in process memory. Supports the
+/// filter, sort and update operators `DaoObj` and `FindQuery` produce and enforces
+/// unique indexes declared through `sync_indexes`. Data is lost on drop.
+#[derive(Default)]
+pub struct MemoryDocumentStore {
+    collections: Mutex<HashMap<String, MemoryCollection>>,
+}
+
+impl MemoryDocumentStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn with_collection<R>(
+        &self,
+        name: &str,
+        f: impl FnOnce(&mut MemoryCollection) -> Result<R>,
+    ) -> Result<R> {
+        let mut collections = self
+            .collections
+            .lock()
+            .map_err(|_| error!("Memory store lock poisoned"))?;
+
+        f(collections.entry(name.to_string()).or_default())
+    }
+}
+
+#[async_trait]
+impl DocumentStore for MemoryDocumentStore {
+    async fn insert_one(&self, collection: &str, mut doc: Document) -> Result<Bson> {
+        if !doc.contains_key("_id") {
+            doc.insert("_id", self.new_id());
+        }
+        let id = doc.get("_id").cloned().unwrap_or(Bson::Null);
+
+        self.with_collection(collection, |col| {
+            col.check_unique(collection, &doc, None)?;
+            col.docs.push(doc);
+            Ok(id)
+        })
+    }
+
+    async fn find_one(&self, collection: &str, filter: Document) -> Result<Option<Document>> {
+        self.with_collection(collection, |col| {
+            for doc in col.docs.iter() {
+                if matches(doc, &filter)? {
+                    return Ok(Some(doc.clone()));
+                }
+            }
+            Ok(None)
+        })
+    }
+
+    async fn find(
+        &self,
+        collection: &str,
+        filter: Document,
+        options: FindOptions,
+    ) -> Result<Vec<Document>> {
+        self.with_collection(collection, |col| select(col.docs.iter(), &filter, &options))
+    }
+
+    async fn count(&self, collection: &str, filter: Document) -> Result<u64> {
+        self.with_collection(collection, |col| {
+            let mut count = 0;
+            for doc in col.docs.iter() {
+                if matches(doc, &filter)? {
+                    count += 1;
+                }
+            }
+            Ok(count)
+        })
+    }
+
+    async fn find_one_and_update(
+        &self,
+        collection: &str,
+        filter: Document,
+        update: Document,
+    ) -> Result<Option<Document>> {
+        self.with_collection(collection, |col| {
+            col.update_first(collection, &filter, &update)
+        })
+    }
+
+    async fn update_one(
+        &self,
+        collection: &str,
+        filter: Document,
+        update: Document,
+    ) -> Result<u64> {
+        self.with_collection(collection, |col| {
+            Ok(col
+                .update_first(collection, &filter, &update)?
+                .map_or(0, |_| 1))
+        })
+    }
+
+    async fn delete_one(&self, collection: &str, filter: Document) -> Result<u64> {
+        self.with_collection(collection, |col| {
+            for i in 0..col.docs.len() {
+                if matches(&col.docs[i], &filter)? {
+                    col.docs.remove(i);
+                    return Ok(1);
+                }
+            }
+            Ok(0)
+        })
+    }
+
+    async fn sync_indexes(
+        &self,
+        collection: &str,
+        specs: &[IndexSpec],
+        drop_undeclared: bool,
+    ) -> Result<()> {
+        self.with_collection(collection, |col| {
+            col.indexes = reconcile(&col.indexes, specs, drop_undeclared);
+            Ok(())
+        })
+    }
+}
+
+impl MemoryCollection {
+    fn update_first(
+        &mut self,
+        name: &str,
+        filter: &Document,
+        update: &Document,
+    ) -> Result<Option<Document>> {
+        let mut position = None;
+        for (i, doc) in self.docs.iter().enumerate() {
+            if matches(doc, filter)? {
+                position = Some(i);
+                break;
+            }
+        }
+
+        let Some(i) = position else {
+            return Ok(None);
+        };
+
+        let mut updated = self.docs[i].clone();
+        apply_update(&mut updated, update)?;
+        self.check_unique(name, &updated, Some(i))?;
+        self.docs[i] = updated.clone();
+
+        Ok(Some(updated))
+    }
+
+    /// Fails with [`DaoError::DuplicateKey`] when `doc` clashes with another
+    /// document on `_id` or on a unique index. `skip` is the position of `doc`
+    /// itself when it is being updated.
+    fn check_unique(&self, name: &str, doc: &Document, skip: Option<usize>) -> Result<()> {
+        let others = self
+            .docs
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| Some(*i) != skip)
+            .map(|(_, d)| d);
+
+        let id = doc.get("_id");
+        let mut unique: Vec<&IndexSpec> = self.indexes.iter().filter(|s| s.unique).collect();
+        unique.retain(|s| index_applies(s, doc));
+
+        for other in others {
+            if other.get("_id") == id {
+                return Err(duplicate_key(name, "_id_", doc, &["_id"]));
+            }
+
+            for spec in unique.iter() {
+                if !index_applies(spec, other) {
+                    continue;
+                }
+
+                let fields: Vec<&str> = spec.keys.keys().map(|k| k.as_str()).collect();
+                let same = fields
+                    .iter()
+                    .all(|f| bson_cmp(lookup(doc, f), lookup(other, f)) == Ordering::Equal);
+                if same {
+                    return Err(duplicate_key(name, &spec.index_name(), doc, &fields));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn duplicate_key(collection: &str, index: &str, doc: &Document, fields: &[&str]) -> anyhow::Error {
+    let mut key = Document::new();
+    for f in fields {
+        key.insert(*f, lookup(doc, f).cloned().unwrap_or(Bson::Null));
+    }
+
+    DaoError::DuplicateKey(format!(
+        "E11000 duplicate key error collection: {} index: {} dup key: {}",
+        collection, index, key
+    ))
+    .into()
+}
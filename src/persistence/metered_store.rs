@@ -0,0 +1,233 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use mongodb::bson::{Bson, Document};
+use mongodb::options::FindOptions;
+use mongodb::ClientSession;
+use tracing::Instrument;
+
+use crate::app::index::IndexSpec;
+use crate::metrics::DAO_OPERATION_DURATION;
+use crate::persistence::document_store::DocumentStore;
+
+/// [`DocumentStore`] decorator running every operation in a `dao` span and
+/// recording its latency in [`DAO_OPERATION_DURATION`] by collection,
+/// operation and outcome.
+pub struct MeteredDocumentStore {
+    inner: Arc<dyn DocumentStore>,
+}
+
+impl MeteredDocumentStore {
+    pub fn new(inner: Arc<dyn DocumentStore>) -> Self {
+        Self { inner }
+    }
+}
+
+async fn timed<R>(
+    collection: &str,
+    operation: &str,
+    f: impl Future<Output = Result<R>>,
+) -> Result<R> {
+    let span = tracing::debug_span!("dao", collection, operation);
+    let start = Instant::now();
+    let result = f.instrument(span).await;
+    let outcome = if result.is_ok() { "ok" } else { "error" };
+
+    DAO_OPERATION_DURATION
+        .with_label_values(&[collection, operation, outcome])
+        .observe(start.elapsed().as_secs_f64());
+
+    result
+}
+
+#[async_trait]
+impl DocumentStore for MeteredDocumentStore {
+    fn new_id(&self) -> Bson {
+        self.inner.new_id()
+    }
+
+    async fn insert_one(&self, collection: &str, doc: Document) -> Result<Bson> {
+        timed(
+            collection,
+            "insert_one",
+            self.inner.insert_one(collection, doc),
+        )
+        .await
+    }
+
+    async fn find_one(&self, collection: &str, filter: Document) -> Result<Option<Document>> {
+        timed(
+            collection,
+            "find_one",
+            self.inner.find_one(collection, filter),
+        )
+        .await
+    }
+
+    async fn find(
+        &self,
+        collection: &str,
+        filter: Document,
+        options: FindOptions,
+    ) -> Result<Vec<Document>> {
+        timed(
+            collection,
+            "find",
+            self.inner.find(collection, filter, options),
+        )
+        .await
+    }
+
+    async fn count(&self, collection: &str, filter: Document) -> Result<u64> {
+        timed(collection, "count", self.inner.count(collection, filter)).await
+    }
+
+    async fn find_one_and_update(
+        &self,
+        collection: &str,
+        filter: Document,
+        update: Document,
+    ) -> Result<Option<Document>> {
+        timed(
+            collection,
+            "find_one_and_update",
+            self.inner.find_one_and_update(collection, filter, update),
+        )
+        .await
+    }
+
+    async fn update_one(
+        &self,
+        collection: &str,
+        filter: Document,
+        update: Document,
+    ) -> Result<u64> {
+        timed(
+            collection,
+            "update_one",
+            self.inner.update_one(collection, filter, update),
+        )
+        .await
+    }
+
+    async fn delete_one(&self, collection: &str, filter: Document) -> Result<u64> {
+        timed(
+            collection,
+            "delete_one",
+            self.inner.delete_one(collection, filter),
+        )
+        .await
+    }
+
+    async fn sync_indexes(
+        &self,
+        collection: &str,
+        specs: &[IndexSpec],
+        drop_undeclared: bool,
+    ) -> Result<()> {
+        timed(
+            collection,
+            "sync_indexes",
+            self.inner.sync_indexes(collection, specs, drop_undeclared),
+        )
+        .await
+    }
+
+    async fn start_session(&self) -> Result<ClientSession> {
+        self.inner.start_session().await
+    }
+
+    async fn insert_one_with_session(
+        &self,
+        session: &mut ClientSession,
+        collection: &str,
+        doc: Document,
+    ) -> Result<Bson> {
+        timed(
+            collection,
+            "insert_one",
+            self.inner.insert_one_with_session(session, collection, doc),
+        )
+        .await
+    }
+
+    async fn find_with_session(
+        &self,
+        session: &mut ClientSession,
+        collection: &str,
+        filter: Document,
+        options: FindOptions,
+    ) -> Result<Vec<Document>> {
+        timed(
+            collection,
+            "find",
+            self.inner
+                .find_with_session(session, collection, filter, options),
+        )
+        .await
+    }
+
+    async fn count_with_session(
+        &self,
+        session: &mut ClientSession,
+        collection: &str,
+        filter: Document,
+    ) -> Result<u64> {
+        timed(
+            collection,
+            "count",
+            self.inner.count_with_session(session, collection, filter),
+        )
+        .await
+    }
+
+    async fn find_one_and_update_with_session(
+        &self,
+        session: &mut ClientSession,
+        collection: &str,
+        filter: Document,
+        update: Document,
+    ) -> Result<Option<Document>> {
+        timed(
+            collection,
+            "find_one_and_update",
+            self.inner
+                .find_one_and_update_with_session(session, collection, filter, update),
+        )
+        .await
+    }
+
+    async fn update_one_with_session(
+        &self,
+        session: &mut ClientSession,
+        collection: &str,
+        filter: Document,
+        update: Document,
+    ) -> Result<u64> {
+        timed(
+            collection,
+            "update_one",
+            self.inner
+                .update_one_with_session(session, collection, filter, update),
+        )
+        .await
+    }
+
+    async fn delete_one_with_session(
+        &self,
+        session: &mut ClientSession,
+        collection: &str,
+        filter: Document,
+    ) -> Result<u64> {
+        timed(
+            collection,
+            "delete_one",
+            self.inner
+                .delete_one_with_session(session, collection, filter),
+        )
+        .await
+    }
+}
@@ -1,5 +1,5 @@
 use anyhow::Result;
-use mongodb::{options::ClientOptions, Client};
+use mongodb::{bson::doc, options::ClientOptions, Client};
 
 use anyhow::anyhow;
 use log;
@@ -50,4 +50,11 @@ impl MongoProvider {
         let db = client.database(&self.mongo_database);
         Ok(db)
     }
+
+    pub async fn ping(&self) -> Result<()> {
+        self.get_database()?
+            .run_command(doc! { "ping": 1 }, None)
+            .await?;
+        Ok(())
+    }
 }
@@ -1,5 +1,6 @@
 use anyhow::{anyhow as error, Result};
 
+#[derive(Clone)]
 pub struct RedisProvider {
     pub connection: Option<redis::aio::ConnectionManager>,
     pub connection_uri: String,
@@ -30,6 +31,14 @@ impl RedisProvider {
             .ok_or(error!("Please connect first then get connection"))
     }
 
+    pub async fn ping(&self) -> Result<()> {
+        let mut conn = self.get_connection()?;
+        redis::cmd("PING")
+            .query_async::<_, String>(&mut conn)
+            .await?;
+        Ok(())
+    }
+
     pub fn get_sync_connection(&self) -> Result<redis::Connection> {
         let client = redis::Client::open(self.connection_uri.to_string())?;
         let conn = client.get_connection()?;
@@ -0,0 +1,44 @@
+use anyhow::{anyhow, Result};
+use sqlx::any::{install_default_drivers, AnyPoolOptions};
+use sqlx::AnyPool;
+
+#[derive(Debug, Clone)]
+pub struct SqlProvider {
+    pub pool: Option<AnyPool>,
+    pub sql_uri: String,
+}
+
+impl SqlProvider {
+    /// `uri` of the SQLite database, e.g. `sqlite://data.db?mode=rwc`.
+    pub fn new(uri: &str) -> Self {
+        Self {
+            pool: None,
+            sql_uri: uri.to_string(),
+        }
+    }
+
+    pub async fn connect(&mut self) -> Result<AnyPool> {
+        if let Some(p) = &self.pool {
+            return Ok(p.clone());
+        }
+
+        install_default_drivers();
+
+        let pool = AnyPoolOptions::new().connect(&self.sql_uri).await?;
+
+        self.pool = Some(pool.clone());
+        log::info!("SQL connected");
+        Ok(pool)
+    }
+
+    pub fn get_pool(&self) -> Result<AnyPool> {
+        self.pool
+            .clone()
+            .ok_or(anyhow!("SQL pool was not found. Please connect first"))
+    }
+
+    pub async fn ping(&self) -> Result<()> {
+        sqlx::query("SELECT 1").execute(&self.get_pool()?).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,743 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use anyhow::{anyhow as error, Result};
+use async_trait::async_trait;
+use mongodb::bson::{oid::ObjectId, Bson, Document};
+use mongodb::options::FindOptions;
+use sqlx::AnyConnection;
+use strum::{Display, EnumString};
+
+use crate::app::dao::DaoError;
+use crate::app::dto::id_from_bson;
+use crate::app::index::{reconcile, IndexSpec};
+use crate::persistence::document_filter::{
+    apply_update, id_hint, index_applies, lookup, matches, select,
+};
+use crate::persistence::document_store::DocumentStore;
+use crate::persistence::sql_provider::SqlProvider;
+
+/// Attempts made by a write whose row changed between read and write.
+const MAX_WRITE_ATTEMPTS: usize = 5;
+
+/// Ids generated for documents inserted without one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum IdType {
+    ObjectId,
+    Uuid,
+}
+
+/// [`DocumentStore`] on a SQLite database through [`SqlProvider`], for local
+/// use and small data sets.
+///
+/// Each collection is a table of `(id, doc)` rows holding the document as
+/// canonical extended JSON, so BSON types survive the round trip. Rows are
+/// narrowed in SQL where the filter allows: lookups by `_id` go straight to the
+/// primary key and equality on every field of a declared index goes through
+/// the `<collection>__index` table. The remaining filter, sort and paging are
+/// evaluated in process like the memory store, so filters no index covers
+/// read the whole table and counts read every match. That rules out large or
+/// shared databases, which is why `AppConfig` only accepts SQLite URIs.
+///
+/// Unique indexes are kept in a `<collection>__keys` table whose primary key
+/// makes the database reject duplicates, and every write is a compare and swap
+/// on the stored document.
+pub struct SqlDocumentStore {
+    provider: SqlProvider,
+    id_type: IdType,
+    tables: Mutex<HashSet<String>>,
+    indexes: Mutex<HashMap<String, Vec<IndexSpec>>>,
+}
+
+struct Row {
+    id: String,
+    raw: String,
+    doc: Document,
+}
+
+enum Write<'a> {
+    Update(&'a Document),
+    Delete,
+}
+
+impl SqlDocumentStore {
+    pub fn new(provider: SqlProvider, id_type: IdType) -> Self {
+        Self {
+            provider,
+            id_type,
+            tables: Mutex::new(HashSet::new()),
+            indexes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn ensure_tables(&self, collection: &str) -> Result<()> {
+        if !collection
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(error!("Invalid collection name `{}`", collection));
+        }
+
+        if self.tables.lock().map_err(poisoned)?.contains(collection) {
+            return Ok(());
+        }
+
+        let mut conn = self.provider.get_pool()?.acquire().await?;
+        sqlx::query(&format!(
+            r#"CREATE TABLE IF NOT EXISTS "{}" (id TEXT PRIMARY KEY, doc TEXT NOT NULL)"#,
+            collection
+        ))
+        .execute(&mut *conn)
+        .await?;
+        sqlx::query(&format!(
+            r#"CREATE TABLE IF NOT EXISTS "{}__keys" (index_name TEXT NOT NULL, key TEXT NOT NULL, id TEXT NOT NULL, PRIMARY KEY (index_name, key))"#,
+            collection
+        ))
+        .execute(&mut *conn)
+        .await?;
+        sqlx::query(&format!(
+            r#"CREATE TABLE IF NOT EXISTS "{}__index" (index_name TEXT NOT NULL, key TEXT NOT NULL, id TEXT NOT NULL, PRIMARY KEY (index_name, key, id))"#,
+            collection
+        ))
+        .execute(&mut *conn)
+        .await?;
+
+        self.tables
+            .lock()
+            .map_err(poisoned)?
+            .insert(collection.to_string());
+        Ok(())
+    }
+
+    fn declared_indexes(&self, collection: &str) -> Result<Vec<IndexSpec>> {
+        let indexes = self.indexes.lock().map_err(poisoned)?;
+        Ok(indexes.get(collection).cloned().unwrap_or_default())
+    }
+
+    fn unique_indexes(&self, collection: &str) -> Result<Vec<IndexSpec>> {
+        let mut specs = self.declared_indexes(collection)?;
+        specs.retain(|s| s.unique);
+        Ok(specs)
+    }
+
+    /// Rows matching `filter`, in primary key order.
+    async fn rows(
+        &self,
+        conn: &mut AnyConnection,
+        collection: &str,
+        filter: &Document,
+    ) -> Result<Vec<Row>> {
+        let specs = self.declared_indexes(collection)?;
+
+        let fetched: Vec<(String, String)> = match (id_hint(filter), index_hint(filter, &specs)) {
+            (Some(id), _) => {
+                sqlx::query_as(&format!(
+                    r#"SELECT id, doc FROM "{}" WHERE id = $1"#,
+                    collection
+                ))
+                .bind(key_of(id)?)
+                .fetch_all(&mut *conn)
+                .await?
+            }
+            (None, Some((index_name, key))) => {
+                sqlx::query_as(&format!(
+                    r#"SELECT d.id, d.doc FROM "{0}" AS d JOIN "{0}__index" AS i ON i.id = d.id WHERE i.index_name = $1 AND i.key = $2 ORDER BY d.id"#,
+                    collection
+                ))
+                .bind(index_name)
+                .bind(key)
+                .fetch_all(&mut *conn)
+                .await?
+            }
+            (None, None) => {
+                sqlx::query_as(&format!(
+                    r#"SELECT id, doc FROM "{}" ORDER BY id"#,
+                    collection
+                ))
+                .fetch_all(&mut *conn)
+                .await?
+            }
+        };
+
+        let mut rows = Vec::new();
+        for (id, raw) in fetched {
+            let doc = decode(&raw)?;
+            if matches(&doc, filter)? {
+                rows.push(Row { id, raw, doc });
+            }
+        }
+
+        Ok(rows)
+    }
+
+    async fn insert_keys(
+        conn: &mut AnyConnection,
+        collection: &str,
+        specs: &[IndexSpec],
+        id: &str,
+        doc: &Document,
+    ) -> Result<()> {
+        for spec in specs.iter().filter(|s| index_applies(s, doc)) {
+            let mut key = Document::new();
+            for field in spec.keys.keys() {
+                key.insert(field, lookup(doc, field).cloned().unwrap_or(Bson::Null));
+            }
+
+            sqlx::query(&format!(
+                r#"INSERT INTO "{}__keys" (index_name, key, id) VALUES ($1, $2, $3)"#,
+                collection
+            ))
+            .bind(spec.index_name())
+            .bind(encode(&key))
+            .bind(id.to_string())
+            .execute(&mut *conn)
+            .await
+            .map_err(|e| map_sql_error(e, collection, &spec.index_name(), &key))?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds the `__index` rows of `doc` for every declared index.
+    async fn insert_index_rows(
+        conn: &mut AnyConnection,
+        collection: &str,
+        specs: &[IndexSpec],
+        id: &str,
+        doc: &Document,
+    ) -> Result<()> {
+        for spec in specs {
+            for key in index_keys(spec, doc) {
+                sqlx::query(&format!(
+                    r#"INSERT INTO "{}__index" (index_name, key, id) VALUES ($1, $2, $3)"#,
+                    collection
+                ))
+                .bind(spec.index_name())
+                .bind(key)
+                .bind(id.to_string())
+                .execute(&mut *conn)
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drops the `__keys` and `__index` rows of document `id`.
+    async fn delete_index_rows(conn: &mut AnyConnection, collection: &str, id: &str) -> Result<()> {
+        for table in ["keys", "index"] {
+            sqlx::query(&format!(
+                r#"DELETE FROM "{}__{}" WHERE id = $1"#,
+                collection, table
+            ))
+            .bind(id.to_string())
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the stored `row` with `doc`, or deletes it when `doc` is `None`,
+    /// unless it changed since `row` was read. Returns whether it was written.
+    async fn swap(
+        conn: &mut AnyConnection,
+        collection: &str,
+        row: &Row,
+        doc: Option<&Document>,
+    ) -> Result<bool> {
+        let written = match doc {
+            Some(doc) => {
+                sqlx::query(&format!(
+                    r#"UPDATE "{}" SET doc = $1 WHERE id = $2 AND doc = $3"#,
+                    collection
+                ))
+                .bind(encode(doc))
+                .bind(row.id.clone())
+                .bind(row.raw.clone())
+                .execute(&mut *conn)
+                .await?
+            }
+            None => {
+                sqlx::query(&format!(
+                    r#"DELETE FROM "{}" WHERE id = $1 AND doc = $2"#,
+                    collection
+                ))
+                .bind(row.id.clone())
+                .bind(row.raw.clone())
+                .execute(&mut *conn)
+                .await?
+            }
+        };
+
+        Ok(written.rows_affected() > 0)
+    }
+
+    /// Applies `write` to the first document matching `filter`. Returns the
+    /// document as written, or as it was before a delete.
+    async fn write_first(
+        &self,
+        collection: &str,
+        filter: &Document,
+        write: Write<'_>,
+    ) -> Result<Option<Document>> {
+        self.ensure_tables(collection).await?;
+        let specs = self.declared_indexes(collection)?;
+        let unique = self.unique_indexes(collection)?;
+        let pool = self.provider.get_pool()?;
+
+        for _ in 0..MAX_WRITE_ATTEMPTS {
+            let mut tx = pool.begin().await?;
+
+            let Some(row) = self
+                .rows(&mut tx, collection, filter)
+                .await?
+                .into_iter()
+                .next()
+            else {
+                return Ok(None);
+            };
+
+            let updated = match &write {
+                Write::Update(update) => {
+                    let mut updated = row.doc.clone();
+                    apply_update(&mut updated, update)?;
+                    Some(updated)
+                }
+                Write::Delete => None,
+            };
+
+            if !Self::swap(&mut tx, collection, &row, updated.as_ref()).await? {
+                // Changed since it was read, look again.
+                tx.rollback().await?;
+                continue;
+            }
+
+            Self::delete_index_rows(&mut tx, collection, &row.id).await?;
+            if let Some(updated) = &updated {
+                Self::insert_keys(&mut tx, collection, &unique, &row.id, updated).await?;
+                Self::insert_index_rows(&mut tx, collection, &specs, &row.id, updated).await?;
+            }
+
+            tx.commit().await?;
+            return Ok(Some(updated.unwrap_or(row.doc)));
+        }
+
+        Err(error!(
+            "Gave up writing to `{}` after {} concurrent modifications",
+            collection, MAX_WRITE_ATTEMPTS
+        ))
+    }
+}
+
+#[async_trait]
+impl DocumentStore for SqlDocumentStore {
+    fn new_id(&self) -> Bson {
+        match self.id_type {
+            IdType::ObjectId => Bson::ObjectId(ObjectId::new()),
+            IdType::Uuid => Bson::String(uuid::Uuid::new_v4().to_string()),
+        }
+    }
+
+    async fn insert_one(&self, collection: &str, mut doc: Document) -> Result<Bson> {
+        self.ensure_tables(collection).await?;
+
+        if !doc.contains_key("_id") {
+            doc.insert("_id", self.new_id());
+        }
+        let id = doc.get("_id").cloned().unwrap_or(Bson::Null);
+        let key = key_of(&id)?;
+
+        let specs = self.declared_indexes(collection)?;
+        let unique = self.unique_indexes(collection)?;
+        let mut tx = self.provider.get_pool()?.begin().await?;
+
+        sqlx::query(&format!(
+            r#"INSERT INTO "{}" (id, doc) VALUES ($1, $2)"#,
+            collection
+        ))
+        .bind(key.clone())
+        .bind(encode(&doc))
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            map_sql_error(
+                e,
+                collection,
+                "_id_",
+                &mongodb::bson::doc! {"_id": id.clone()},
+            )
+        })?;
+
+        Self::insert_keys(&mut tx, collection, &unique, &key, &doc).await?;
+        Self::insert_index_rows(&mut tx, collection, &specs, &key, &doc).await?;
+        tx.commit().await?;
+
+        Ok(id)
+    }
+
+    async fn find_one(&self, collection: &str, filter: Document) -> Result<Option<Document>> {
+        self.ensure_tables(collection).await?;
+        let mut conn = self.provider.get_pool()?.acquire().await?;
+
+        let rows = self.rows(&mut conn, collection, &filter).await?;
+        Ok(rows.into_iter().next().map(|r| r.doc))
+    }
+
+    async fn find(
+        &self,
+        collection: &str,
+        filter: Document,
+        options: FindOptions,
+    ) -> Result<Vec<Document>> {
+        self.ensure_tables(collection).await?;
+        let mut conn = self.provider.get_pool()?.acquire().await?;
+
+        let rows = self.rows(&mut conn, collection, &filter).await?;
+        select(rows.iter().map(|r| &r.doc), &Document::new(), &options)
+    }
+
+    async fn count(&self, collection: &str, filter: Document) -> Result<u64> {
+        self.ensure_tables(collection).await?;
+        let mut conn = self.provider.get_pool()?.acquire().await?;
+
+        Ok(self.rows(&mut conn, collection, &filter).await?.len() as u64)
+    }
+
+    async fn find_one_and_update(
+        &self,
+        collection: &str,
+        filter: Document,
+        update: Document,
+    ) -> Result<Option<Document>> {
+        self.write_first(collection, &filter, Write::Update(&update))
+            .await
+    }
+
+    async fn update_one(
+        &self,
+        collection: &str,
+        filter: Document,
+        update: Document,
+    ) -> Result<u64> {
+        let updated = self
+            .write_first(collection, &filter, Write::Update(&update))
+            .await?;
+        Ok(updated.map_or(0, |_| 1))
+    }
+
+    async fn delete_one(&self, collection: &str, filter: Document) -> Result<u64> {
+        let deleted = self.write_first(collection, &filter, Write::Delete).await?;
+        Ok(deleted.map_or(0, |_| 1))
+    }
+
+    /// Registers the indexes of `collection` and rebuilds its key and index
+    /// tables, failing if stored documents already violate a unique index.
+    async fn sync_indexes(
+        &self,
+        collection: &str,
+        specs: &[IndexSpec],
+        drop_undeclared: bool,
+    ) -> Result<()> {
+        self.ensure_tables(collection).await?;
+        {
+            let mut indexes = self.indexes.lock().map_err(poisoned)?;
+            let existing = indexes.remove(collection).unwrap_or_default();
+            indexes.insert(
+                collection.to_string(),
+                reconcile(&existing, specs, drop_undeclared),
+            );
+        }
+
+        let specs = self.declared_indexes(collection)?;
+        let unique = self.unique_indexes(collection)?;
+        let mut tx = self.provider.get_pool()?.begin().await?;
+
+        for table in ["keys", "index"] {
+            sqlx::query(&format!(r#"DELETE FROM "{}__{}""#, collection, table))
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        for row in self.rows(&mut tx, collection, &Document::new()).await? {
+            Self::insert_keys(&mut tx, collection, &unique, &row.id, &row.doc).await?;
+            Self::insert_index_rows(&mut tx, collection, &specs, &row.id, &row.doc).await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+fn poisoned<E>(_: E) -> anyhow::Error {
+    error!("SQL store lock poisoned")
+}
+
+fn key_of(id: &Bson) -> Result<String> {
+    id_from_bson(id).ok_or(error!("Unsupported id `{}`", id))
+}
+
+fn encode(doc: &Document) -> String {
+    Bson::Document(doc.clone())
+        .into_canonical_extjson()
+        .to_string()
+}
+
+/// Key values of `spec` on `doc`, as stored in the `__index` table. An array
+/// field is keyed by each element and by the array itself, like a Mongo
+/// multikey index, and numbers are keyed as doubles so `1` and `1.0` meet.
+fn index_keys(spec: &IndexSpec, doc: &Document) -> Vec<String> {
+    let mut keys = vec![Document::new()];
+    for field in spec.keys.keys() {
+        let value = lookup(doc, field).cloned().unwrap_or(Bson::Null);
+        let mut values = vec![];
+        if let Bson::Array(items) = &value {
+            values.extend(items.iter().cloned());
+        }
+        values.push(value);
+
+        keys = keys
+            .into_iter()
+            .flat_map(|key| {
+                values.iter().map(move |v| {
+                    let mut key = key.clone();
+                    key.insert(field, key_value(v));
+                    key
+                })
+            })
+            .collect();
+    }
+
+    let mut keys: Vec<String> = keys.iter().map(encode).collect();
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+fn key_value(value: &Bson) -> Bson {
+    match value {
+        Bson::Int32(v) => Bson::Double(*v as f64),
+        Bson::Int64(v) => Bson::Double(*v as f64),
+        v => v.clone(),
+    }
+}
+
+/// An index of `specs` whose every field `filter` pins by plain equality, with
+/// the `__index` key to look up. Every document the filter matches is under
+/// that key, the filter still runs on the rows found.
+fn index_hint(filter: &Document, specs: &[IndexSpec]) -> Option<(String, String)> {
+    let mut pinned = Document::new();
+    equalities(filter, &mut pinned);
+
+    specs
+        .iter()
+        .filter(|s| !s.keys.values().any(|v| v.as_str() == Some("text")))
+        .find_map(|spec| {
+            let mut key = Document::new();
+            for field in spec.keys.keys() {
+                key.insert(field, key_value(pinned.get(field)?));
+            }
+            Some((spec.index_name(), encode(&key)))
+        })
+}
+
+/// Plain equality conditions of `filter`, top level or inside `$and`, on
+/// values an `__index` lookup finds every match of.
+fn equalities(filter: &Document, pinned: &mut Document) {
+    for (field, value) in filter {
+        if field == "$and" {
+            for f in value.as_array().into_iter().flatten() {
+                if let Some(f) = f.as_document() {
+                    equalities(f, pinned);
+                }
+            }
+            continue;
+        }
+
+        let plain = matches!(
+            value,
+            Bson::String(_)
+                | Bson::ObjectId(_)
+                | Bson::Boolean(_)
+                | Bson::DateTime(_)
+                | Bson::Int32(_)
+                | Bson::Int64(_)
+                | Bson::Double(_)
+                | Bson::Null
+        );
+        if !field.starts_with('$') && plain {
+            pinned.insert(field, value.clone());
+        }
+    }
+}
+
+fn decode(raw: &str) -> Result<Document> {
+    let value: serde_json::Value = serde_json::from_str(raw)?;
+    match Bson::try_from(value)? {
+        Bson::Document(doc) => Ok(doc),
+        other => Err(error!("Stored value is not a document: {}", other)),
+    }
+}
+
+fn map_sql_error(e: sqlx::Error, collection: &str, index: &str, key: &Document) -> anyhow::Error {
+    match &e {
+        sqlx::Error::Database(db) if db.is_unique_violation() => DaoError::DuplicateKey(format!(
+            "duplicate key error collection: {} index: {} dup key: {}",
+            collection, index, key
+        ))
+        .into(),
+        _ => e.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::doc;
+
+    /// Store on a fresh SQLite file, removed when the returned path drops.
+    async fn sqlite_store() -> (SqlDocumentStore, TempDb) {
+        let path = std::env::temp_dir().join(format!("sql_store_{}.db", uuid::Uuid::new_v4()));
+        let mut provider = SqlProvider::new(&format!("sqlite://{}?mode=rwc", path.display()));
+        provider.connect().await.unwrap();
+
+        (
+            SqlDocumentStore::new(provider, IdType::ObjectId),
+            TempDb(path),
+        )
+    }
+
+    struct TempDb(std::path::PathBuf);
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn crud_round_trip() {
+        let (store, _db) = sqlite_store().await;
+
+        let id = store
+            .insert_one("todo", doc! {"title": "a", "done": false, "n": 1})
+            .await
+            .unwrap();
+        store
+            .insert_one("todo", doc! {"title": "b", "done": true, "n": 2})
+            .await
+            .unwrap();
+
+        let found = store
+            .find_one("todo", doc! {"_id": id.clone()})
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.get_str("title").unwrap(), "a");
+
+        let opt = FindOptions::builder().sort(doc! {"n": -1}).limit(1).build();
+        let first = store.find("todo", doc! {}, opt).await.unwrap();
+        assert_eq!(first[0].get_str("title").unwrap(), "b");
+        assert_eq!(store.count("todo", doc! {"done": false}).await.unwrap(), 1);
+
+        let updated = store
+            .find_one_and_update(
+                "todo",
+                doc! {"_id": id.clone()},
+                doc! {"$set": {"done": true}, "$inc": {"n": 10}},
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(updated.get_bool("done").unwrap());
+        assert_eq!(updated.get_i32("n").unwrap(), 11);
+
+        assert_eq!(store.delete_one("todo", doc! {"_id": id}).await.unwrap(), 1);
+        assert_eq!(store.count("todo", doc! {}).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn unique_index_rejects_duplicates() {
+        let (store, _db) = sqlite_store().await;
+        let specs = [IndexSpec::new().asc("email").unique().live()];
+        store.sync_indexes("user", &specs, false).await.unwrap();
+
+        let live = doc! {"email": "a@b.com", "deleted_at": null};
+        let id = store.insert_one("user", live.clone()).await.unwrap();
+
+        let err = store.insert_one("user", live.clone()).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<DaoError>(),
+            Some(DaoError::DuplicateKey(_))
+        ));
+
+        // Trashed documents are outside the live index.
+        store
+            .update_one(
+                "user",
+                doc! {"_id": id},
+                doc! {"$set": {"deleted_at": mongodb::bson::DateTime::now()}},
+            )
+            .await
+            .unwrap();
+        store.insert_one("user", live).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn index_lookup_finds_every_match() {
+        let (store, _db) = sqlite_store().await;
+        let specs = [IndexSpec::new().asc("tag")];
+
+        store.insert_one("post", doc! {"tag": "a"}).await.unwrap();
+        store
+            .insert_one("post", doc! {"tag": ["a", "b"]})
+            .await
+            .unwrap();
+        store.insert_one("post", doc! {"tag": 1}).await.unwrap();
+        store.insert_one("post", doc! {"other": 1}).await.unwrap();
+        store.sync_indexes("post", &specs, false).await.unwrap();
+        store.insert_one("post", doc! {"tag": "a"}).await.unwrap();
+
+        let filter = doc! {"$and": [{"tag": "a"}, {"other": null}]};
+        assert!(index_hint(&filter, &specs).is_some());
+        assert_eq!(store.count("post", filter).await.unwrap(), 3);
+        assert_eq!(store.count("post", doc! {"tag": 1.0}).await.unwrap(), 1);
+        assert_eq!(store.count("post", doc! {"tag": null}).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn stale_write_is_retried_on_the_current_document() {
+        let (store, _db) = sqlite_store().await;
+        let id = store.insert_one("counter", doc! {"n": 0}).await.unwrap();
+        let filter = doc! {"_id": id.clone()};
+
+        let pool = store.provider.get_pool().unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        let stale = store
+            .rows(&mut conn, "counter", &filter)
+            .await
+            .unwrap()
+            .remove(0);
+
+        // Another writer gets in between the read and the write.
+        store
+            .update_one("counter", filter.clone(), doc! {"$inc": {"n": 1}})
+            .await
+            .unwrap();
+
+        let mut lost = stale.doc.clone();
+        apply_update(&mut lost, &doc! {"$inc": {"n": 1}}).unwrap();
+        let written = SqlDocumentStore::swap(&mut conn, "counter", &stale, Some(&lost))
+            .await
+            .unwrap();
+        assert!(!written);
+
+        let current = store
+            .find_one_and_update("counter", filter, doc! {"$inc": {"n": 1}})
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(current.get_i32("n").unwrap(), 2);
+    }
+}
@@ -6,21 +6,24 @@ use crate::application_factory::{self, ApplicationFactory};
 use crate::websocket::redis_pubsub::RedisPubsubAdapter;
 use axum::{routing::get, Json, Router};
 use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tracing::Instrument;
 
 use crate::app::application_dao;
 use crate::app::application_service;
+use crate::telemetry::RequestIdLayer;
+use crate::websocket::messages::{self, RoomEnvelope};
 use crate::websocket::websocket_handler::websocket_handler;
 use crate::websocket::websocket_server::WebsocketServer;
 
 use crate::app::user;
-
-#[derive(Debug, Serialize, Clone)]
-struct ServerResponse<T> {
-    message: String,
-    result: T,
-    code: u32,
-}
+use crate::auth;
+use crate::health;
+use crate::metrics::{self, HttpMetricsLayer, PUBSUB_MESSAGES};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct HelloResponse {
@@ -32,66 +35,163 @@ pub struct ServerState {
     pub application_dao: Arc<ApplicationDao>,
     pub application_service: Arc<ApplicationService>,
     pub websocke_server: Arc<Mutex<WebsocketServer>>,
+    pub pubsub_adapter: Arc<Mutex<RedisPubsubAdapter>>,
 }
 
 async fn adapter_loop(
-    mut adapt: RedisPubsubAdapter,
+    adapt: Arc<Mutex<RedisPubsubAdapter>>,
     state: Arc<Mutex<WebsocketServer>>,
+    mut shutdown: watch::Receiver<bool>,
 ) -> Result<()> {
-    let mut resv = adapt.run()?;
+    let mut resv = adapt
+        .lock()
+        .map_err(|e| anyhow!("Adapter lock error: {}", e))?
+        .run()?;
 
     log::info!("Adapter running!!!");
 
-    while let Some(payload) = resv.recv().await {
-        log::info!(
-            "Got payload adapter loop: {}: {}",
-            payload.channel,
-            payload.data
+    loop {
+        let payload = tokio::select! {
+            payload = resv.recv() => match payload {
+                Some(payload) => payload,
+                None => break,
+            },
+            _ = shutdown.changed() => {
+                adapt
+                    .lock()
+                    .map_err(|e| anyhow!("Adapter lock error: {}", e))?
+                    .stop()?;
+                break;
+            }
+        };
+
+        PUBSUB_MESSAGES.with_label_values(&["received"]).inc();
+
+        let (trace, message) = RoomEnvelope::parse(&payload.data);
+        let span = tracing::info_span!(
+            "pubsub_message",
+            channel = %payload.channel,
+            request_id = trace.as_ref().map(|t| t.request_id.as_str()),
+            sender = trace.as_ref().and_then(|t| t.client_id.as_deref()),
         );
-        let channel_split: Vec<String> =
-            payload.channel.split("::").map(|e| e.to_string()).collect();
 
-        if channel_split.len() < 2 {
-            continue;
+        deliver_to_room(&payload.channel, &message, state.clone())
+            .instrument(span)
+            .await;
+    }
+
+    log::info!("Exiting adapter loop...");
+
+    Ok(())
+}
+
+/// Sends a message received on `room::<name>` to the local members of the room.
+async fn deliver_to_room(channel: &str, message: &str, state: Arc<Mutex<WebsocketServer>>) {
+    log::info!(
+        "Got payload adapter loop: {} ({} bytes)",
+        channel,
+        message.len()
+    );
+
+    let channel_split: Vec<String> = channel.split("::").map(|e| e.to_string()).collect();
+
+    if channel_split.len() < 2 {
+        return;
+    }
+
+    let room_name = channel_split[1].to_string();
+    if room_name.is_empty() {
+        return;
+    }
+
+    let room = match state.lock() {
+        Ok(mut v) => match v.get_room(room_name.as_str()) {
+            Some(v) => v,
+            None => {
+                log::error!("Did not get room: {}", channel);
+                return;
+            }
+        },
+        Err(e) => {
+            log::error!("State lock error: {}", e.to_string());
+            return;
         }
+    };
+
+    if let Err(e) = room.send(message).await {
+        log::error!("Unable to send to room: {}", e.to_string());
+    }
+}
 
-        let room_name = channel_split[1].to_string();
-        if room_name.is_empty() {
-            continue;
+/// Resolves on SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            log::error!("Unable to listen for SIGINT: {}", e.to_string());
+            std::future::pending::<()>().await;
         }
+    };
 
-        let room = match state.lock() {
-            Ok(mut v) => match v.get_room(room_name.as_str()) {
-                Some(v) => v,
-                None => {
-                    log::error!("Did not get room: {}", payload.channel);
-                    continue;
-                }
-            },
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut s) => {
+                s.recv().await;
+            }
             Err(e) => {
-                log::error!("State lock error: {}", e.to_string());
-                continue;
+                log::error!("Unable to listen for SIGTERM: {}", e.to_string());
+                std::future::pending::<()>().await;
             }
-        };
-
-        if let Err(e) = room.send(payload.data.as_str()).await {
-            log::error!("Unable to send to room: {}", e.to_string());
-            continue;
         }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
     }
+}
 
-    log::info!("Exiting adapter loop...");
+/// Sends a close frame to every socket and waits until they have all left or
+/// `deadline` passes.
+async fn close_websockets(state: Arc<Mutex<WebsocketServer>>, deadline: Instant) {
+    if let Err(e) = messages::send_shutdown_messages(state.clone()) {
+        log::error!("Unable to close websockets: {}", e.to_string());
+        return;
+    }
 
-    Ok(())
+    while Instant::now() < deadline {
+        let open = state.lock().map(|v| v.client_count()).unwrap_or(0);
+        if open == 0 {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    log::warn!("Websockets still open at the shutdown deadline");
 }
 
+/// Starts the server. The returned handle resolves once the server has shut
+/// down after SIGINT or SIGTERM: new connections are refused, websockets are
+/// closed, the pubsub adapter is stopped and in flight requests get
+/// `shutdown_timeout_seconds` to finish.
 pub async fn server(
     address: &str,
     fac: Arc<Mutex<ApplicationFactory>>,
 ) -> Result<tokio::task::JoinHandle<()>> {
-    let websocket_server = Arc::new(Mutex::new(WebsocketServer::new(fac.clone())));
+    let config = fac
+        .lock()
+        .map_err(|e| anyhow!("Factory lock error: {}", e))?
+        .config
+        .clone();
+    auth::keys::init(&config)?;
 
-    let fac2 = application_factory::ApplicationFactory::new().await?;
+    let websocket_server = Arc::new(Mutex::new(WebsocketServer::new()));
+
+    let fac2 = application_factory::ApplicationFactory::new(config.clone()).await?;
     let fac2 = Arc::new(fac2);
 
     let application_dao = Arc::new(
@@ -105,11 +205,14 @@ pub async fn server(
             .expect("Unable to init application factory"),
     );
 
+    let pubsub_adapter = Arc::new(Mutex::new(RedisPubsubAdapter::new("room::*", fac.clone())));
+
     let server_state = Arc::new(ServerState {
         application_service: application_service.clone(),
         application_dao: application_dao.clone(),
         appliction_factory: fac.clone(),
         websocke_server: websocket_server.clone(),
+        pubsub_adapter: pubsub_adapter.clone(),
     });
 
     let app = Router::new()
@@ -120,23 +223,67 @@ pub async fn server(
                 result: address.to_string(),
             })),
         )
+        .route("/.well-known/jwks.json", get(auth::keys::jwks))
+        .nest("/health", health::health_routes())
+        .route("/metrics", get(metrics::metrics))
         .nest("/user", user::user_routes::user_routes())
         .route("/ws", get(websocket_handler))
+        .layer(HttpMetricsLayer)
+        .layer(RequestIdLayer)
         .with_state(server_state);
 
     //.with_state(server_state);
 
-    log::info!("Serving on {}", address);
-    let addr = address.to_string();
+    let addr: SocketAddr = address.parse()?;
+    let shutdown_timeout = Duration::from_secs(config.shutdown_timeout_seconds);
+    let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
 
-    let adapter = RedisPubsubAdapter::new("room::*", fac.clone());
+    let adapter_handle = tokio::spawn(adapter_loop(
+        pubsub_adapter,
+        websocket_server.clone(),
+        shutdown_rx.clone(),
+    ));
+
+    let server = axum::Server::try_bind(&addr)?
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(async move {
+            shutdown_signal().await;
+            log::info!("Shutdown signal received, draining connections");
+            let _ = shutdown_tx.send(true);
+        });
+
+    log::info!("Serving on {}", address);
 
-    tokio::spawn(adapter_loop(adapter, websocket_server.clone()));
     let handler = tokio::spawn(async move {
-        axum::Server::bind(&addr.parse().unwrap())
-            .serve(app.into_make_service())
-            .await
-            .unwrap();
+        tokio::pin!(server);
+
+        tokio::select! {
+            result = &mut server => {
+                if let Err(e) = result {
+                    log::error!("Server error: {}", e.to_string());
+                }
+                return;
+            }
+            _ = shutdown_rx.changed() => {}
+        }
+
+        // No longer accepting, close the upgraded sockets hyper does not track
+        // while it drains the remaining requests.
+        let deadline = Instant::now() + shutdown_timeout;
+        close_websockets(websocket_server, deadline).await;
+
+        match tokio::time::timeout_at(deadline, adapter_handle).await {
+            Ok(Ok(Err(e))) => log::error!("Adapter loop error: {}", e.to_string()),
+            Ok(Err(e)) => log::error!("Adapter loop panicked: {}", e.to_string()),
+            Err(_) => log::warn!("Adapter loop still running at the shutdown deadline"),
+            Ok(Ok(Ok(()))) => {}
+        }
+
+        match tokio::time::timeout_at(deadline, server).await {
+            Ok(Err(e)) => log::error!("Server error: {}", e.to_string()),
+            Err(_) => log::warn!("Dropping requests still in flight at the shutdown deadline"),
+            Ok(Ok(())) => {}
+        }
     });
 
     Ok(handler)
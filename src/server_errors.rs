@@ -1,74 +1,217 @@
 use axum::{
-    http::StatusCode,
+    http::{header, HeaderValue, StatusCode},
     response::{IntoResponse, Json, Response},
 };
 
-use crate::server_errors;
+use crate::app::dao::DaoError;
+use crate::auth::refresh_token::RefreshTokenError;
+use crate::persistence::document_store::duplicate_key_message;
 use serde::Serialize;
 use thiserror::Error;
 
-#[derive(Debug, Serialize, Clone)]
-struct ServerErrorResponse {
-    message: String,
-    error: ServerError,
-    code: u32,
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// One rejected input field of a [`ServerError::Validation`].
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: &str) -> Self {
+        Self {
+            field: field.to_string(),
+            message: message.to_string(),
+        }
+    }
 }
 
-#[derive(Debug, Error, Serialize, Clone)]
+#[derive(Debug, Error, Clone)]
 pub enum ServerError {
     #[error("UnAuthorized: `{0}`")]
     Unauthorized(String),
 
+    #[error("Forbidden: `{0}`")]
+    Forbidden(String),
+
+    #[error("Not Found: `{0}`")]
+    NotFound(String),
+
+    #[error("Conflict: `{0}`")]
+    Conflict(String),
+
     #[error("Bad Request: `{0}`")]
     BadRequest(String),
 
+    #[error("Validation failed: {}", field_list(.0))]
+    Validation(Vec<FieldError>),
+
+    /// A dependency (database, redis) could not be reached.
+    #[error("Unavailable: `{0}`")]
+    Unavailable(String),
+
     #[error("Internal Error: `{0}`")]
     Internal(String),
 }
 
-impl From<ServerError> for ServerErrorResponse {
-    fn from(value: ServerError) -> ServerErrorResponse {
-        match value {
-            ServerError::Unauthorized(_) => ServerErrorResponse {
-                message: "Unauthorized".to_string(),
-                code: 401u32,
-                error: value,
-            },
-            ServerError::BadRequest(_) => ServerErrorResponse {
-                message: "Bad Request".to_string(),
-                code: 400u32,
-                error: value,
-            },
-            ServerError::Internal(_) => ServerErrorResponse {
-                message: "Internal server error".to_string(),
-                code: 401u32,
-                error: value,
-            },
+fn field_list(errors: &[FieldError]) -> String {
+    errors
+        .iter()
+        .map(|e| format!("`{}` {}", e.field, e.message))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+impl ServerError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Machine readable code sent as `code` in problem responses. Clients match
+    /// on it, so existing codes must never change.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Unauthorized(_) => "unauthorized",
+            Self::Forbidden(_) => "forbidden",
+            Self::NotFound(_) => "not_found",
+            Self::Conflict(_) => "conflict",
+            Self::BadRequest(_) => "bad_request",
+            Self::Validation(_) => "validation_failed",
+            Self::Unavailable(_) => "unavailable",
+            Self::Internal(_) => "internal",
+        }
+    }
+
+    /// Maps any error to the variant clients should see: server and DAO errors
+    /// by kind, refused refresh tokens as `Unauthorized`, unreachable Mongo,
+    /// redis or SQL as `Unavailable` and everything else as `Internal`.
+    pub fn classify(e: &anyhow::Error) -> Self {
+        if let Some(e) = e.downcast_ref::<ServerError>() {
+            return e.clone();
+        }
+
+        if let Some(dao) = e.downcast_ref::<DaoError>() {
+            return match dao {
+                DaoError::Conflict { .. } | DaoError::DuplicateKey(_) => {
+                    Self::Conflict(dao.to_string())
+                }
+                DaoError::NotFound { .. } => Self::NotFound(dao.to_string()),
+                DaoError::Invalid(_) => Self::BadRequest(dao.to_string()),
+            };
+        }
+
+        if let Some(e) = e.downcast_ref::<RefreshTokenError>() {
+            return Self::Unauthorized(e.to_string());
+        }
+
+        if let Some(mongo) = e.downcast_ref::<mongodb::error::Error>() {
+            use mongodb::error::ErrorKind;
+
+            if let Some(message) = duplicate_key_message(mongo) {
+                return Self::Conflict(DaoError::DuplicateKey(message).to_string());
+            }
+            if matches!(
+                *mongo.kind,
+                ErrorKind::ServerSelection { .. }
+                    | ErrorKind::Io(_)
+                    | ErrorKind::ConnectionPoolCleared { .. }
+            ) {
+                return Self::Unavailable(mongo.to_string());
+            }
+        }
+
+        if let Some(redis) = e.downcast_ref::<redis::RedisError>() {
+            if redis.is_io_error()
+                || redis.is_connection_refusal()
+                || redis.is_connection_dropped()
+                || redis.is_timeout()
+            {
+                return Self::Unavailable(redis.to_string());
+            }
+        }
+
+        if let Some(sql) = e.downcast_ref::<sqlx::Error>() {
+            if matches!(
+                sql,
+                sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed | sqlx::Error::Io(_)
+            ) {
+                return Self::Unavailable(sql.to_string());
+            }
+        }
+
+        Self::Internal(e.to_string())
+    }
+}
+
+/// RFC 7807 problem details, sent as `application/problem+json`.
+#[derive(Debug, Serialize, Clone)]
+pub struct Problem {
+    /// Always `about:blank`, the problem kind is in `code`.
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    pub code: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+}
+
+impl From<&ServerError> for Problem {
+    fn from(value: &ServerError) -> Problem {
+        let status = value.status();
+
+        // Dependency and internal failures are logged, not shown to clients.
+        let (detail, errors) = match value {
+            ServerError::Unauthorized(m)
+            | ServerError::Forbidden(m)
+            | ServerError::NotFound(m)
+            | ServerError::Conflict(m)
+            | ServerError::BadRequest(m) => (m.clone(), vec![]),
+            ServerError::Validation(errors) => {
+                ("Request has invalid fields".to_string(), errors.clone())
+            }
+            ServerError::Unavailable(_) => (
+                "Service temporarily unavailable. Try again later".to_string(),
+                vec![],
+            ),
+            ServerError::Internal(_) => ("Internal server error. Check logs".to_string(), vec![]),
+        };
+
+        Problem {
+            problem_type: "about:blank".to_string(),
+            title: status.canonical_reason().unwrap_or("Error").to_string(),
+            status: status.as_u16(),
+            detail,
+            code: value.code(),
+            errors,
         }
     }
 }
 
 impl IntoResponse for ServerError {
     fn into_response(self) -> Response {
-        match self {
-            Self::Unauthorized(_) => (
-                StatusCode::UNAUTHORIZED,
-                Json(ServerErrorResponse::from(self)),
-            )
-                .into_response(),
-            Self::BadRequest(_) => (
-                StatusCode::BAD_REQUEST,
-                Json(ServerErrorResponse::from(self)),
-            )
-                .into_response(),
-            _ => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ServerErrorResponse::from(ServerError::Internal(
-                    "Internal server error. Check  logs".to_string(),
-                ))),
-            )
-                .into_response(),
+        match &self {
+            Self::Internal(_) | Self::Unavailable(_) => log::error!("{}", self),
+            _ => log::debug!("{}", self),
         }
+
+        let mut response = (self.status(), Json(Problem::from(&self))).into_response();
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+
+        response
     }
 }
 
@@ -76,9 +219,7 @@ pub struct AppError(pub anyhow::Error);
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        let msg = format!("Something went wrong: {}", self.0);
-
-        ServerError::BadRequest(msg).into_response()
+        ServerError::classify(&self.0).into_response()
     }
 }
 
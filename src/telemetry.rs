@@ -0,0 +1,158 @@
+pub mod redact;
+
+use std::convert::Infallible;
+use std::task::{Context, Poll};
+
+use anyhow::{anyhow as error, Result};
+use async_trait::async_trait;
+use axum::{
+    extract::{FromRequestParts, MatchedPath},
+    http::{request::Parts, HeaderValue, Request},
+    response::Response,
+};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use tower::{Layer, Service};
+use tracing::Instrument;
+use tracing_subscriber::EnvFilter;
+use uuid::Uuid;
+
+use crate::config::{AppConfig, LogFormat};
+use redact::{RedactingMakeWriter, Redactor};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest accepted incoming `X-Request-Id`, longer ones are replaced.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Installs the global subscriber writing `log_format` lines to stdout at the
+/// `log_level` filter. Records from the `log` macros are forwarded, so they
+/// carry the current span, and every line goes through a [`Redactor`].
+pub fn init_subscriber(config: &AppConfig) -> Result<()> {
+    let filter = EnvFilter::try_new(&config.log_level)?;
+    let writer =
+        RedactingMakeWriter::new(std::io::stdout, Redactor::new(&config.log_redact_fields));
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer);
+
+    match config.log_format {
+        LogFormat::Json => builder.json().with_current_span(true).try_init(),
+        // Colors would split field names from their values for the redactor.
+        LogFormat::Text => builder.with_ansi(false).try_init(),
+    }
+    .map_err(|e| error!("Unable to install the log subscriber: {}", e))
+}
+
+pub fn new_request_id() -> String {
+    Uuid::new_v4().to_string()
+}
+
+fn valid_request_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.chars().all(|c| c.is_ascii_graphic())
+}
+
+/// Id of the current HTTP request, set by [`RequestIdLayer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for RequestId
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<RequestId>()
+            .cloned()
+            .unwrap_or_else(|| RequestId(new_request_id())))
+    }
+}
+
+/// Carried with messages that cross process boundaries, e.g. room messages
+/// published to redis, so the receiving side logs under the same request id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceContext {
+    pub request_id: String,
+    /// Websocket client the message originates from.
+    pub client_id: Option<String>,
+}
+
+/// Takes `X-Request-Id` from the request or generates one, runs the request in
+/// an `http_request` span carrying it and echoes it on the response.
+#[derive(Debug, Clone, Default)]
+pub struct RequestIdLayer;
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = RequestIdService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestIdService { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestIdService<S> {
+    inner: S,
+}
+
+impl<S, B> Service<Request<B>> for RequestIdService<S>
+where
+    S: Service<Request<B>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        // Take the service that was driven to readiness and leave a clone behind.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| valid_request_id(v))
+            .map(|v| v.to_string())
+            .unwrap_or_else(new_request_id);
+        req.extensions_mut().insert(RequestId(request_id.clone()));
+
+        let route = req
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|p| p.as_str().to_string())
+            .unwrap_or_else(|| req.uri().path().to_string());
+        let span = tracing::info_span!(
+            "http_request",
+            request_id = %request_id,
+            method = %req.method(),
+            route = %route,
+        );
+
+        Box::pin(
+            async move {
+                let mut response = inner.call(req).await?;
+
+                tracing::debug!(status = response.status().as_u16(), "request finished");
+
+                if let Ok(value) = HeaderValue::from_str(&request_id) {
+                    response.headers_mut().insert(REQUEST_ID_HEADER, value);
+                }
+
+                Ok(response)
+            }
+            .instrument(span),
+        )
+    }
+}
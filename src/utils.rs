@@ -7,7 +7,7 @@ pub fn generate_unique_id() -> Result<String, SystemTimeError> {
     let timestamp = now.duration_since(UNIX_EPOCH)?.as_secs();
     let random_id = Uuid::new_v4().simple().to_string();
     let uid = format!("{}-{}", timestamp, random_id);
-    return Ok(uid);
+    Ok(uid)
 }
 
 pub enum TimeUnit {
@@ -23,5 +23,5 @@ pub fn get_current_timestamp() -> Result<SECONDS, String> {
         Ok(v) => v,
     };
     let elasped = iat.as_secs();
-    return Ok(SECONDS(elasped));
+    Ok(SECONDS(elasped))
 }
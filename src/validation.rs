@@ -0,0 +1,188 @@
+use async_trait::async_trait;
+use axum::{
+    body::HttpBody,
+    extract::{rejection::JsonRejection, FromRequest, Json},
+    http::Request,
+    BoxError,
+};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use validator::{Validate, ValidationError, ValidationErrors, ValidationErrorsKind};
+
+use crate::server_errors::{FieldError, ServerError};
+
+/// Deserializes a JSON body into `T` and runs its [`Validate`] rules. Malformed
+/// bodies are rejected with `bad_request`, failed rules with
+/// `validation_failed` listing every invalid field.
+///
+/// ```ignore
+/// #[derive(Deserialize, Validate)]
+/// struct Signup {
+///     #[validate(email)]
+///     email: String,
+/// }
+///
+/// async fn signup(ValidatedJson(payload): ValidatedJson<Signup>) {}
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidatedJson<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = ServerError;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state)
+            .await
+            .map_err(|e: JsonRejection| ServerError::BadRequest(e.body_text()))?;
+
+        value.validate()?;
+
+        Ok(Self(value))
+    }
+}
+
+impl From<ValidationErrors> for ServerError {
+    fn from(errors: ValidationErrors) -> Self {
+        ServerError::Validation(field_errors(&errors))
+    }
+}
+
+/// Flattens `errors` into one [`FieldError`] per failed rule. Nested fields are
+/// named by path, `address.city` or `items[2].name`, and sorted so responses
+/// are stable.
+pub fn field_errors(errors: &ValidationErrors) -> Vec<FieldError> {
+    let mut result = vec![];
+    collect(errors, "", &mut result);
+    result.sort_by(|a, b| a.field.cmp(&b.field));
+    result
+}
+
+fn collect(errors: &ValidationErrors, prefix: &str, result: &mut Vec<FieldError>) {
+    for (field, kind) in errors.errors() {
+        // Struct level rules (`#[validate(schema(...))]`) are reported on the
+        // struct itself.
+        let path = match (*field, prefix) {
+            ("__all__", "") => "body".to_string(),
+            ("__all__", _) => prefix.to_string(),
+            (_, "") => field.to_string(),
+            (_, _) => format!("{}.{}", prefix, field),
+        };
+
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                result.extend(errors.iter().map(|e| FieldError {
+                    field: path.clone(),
+                    message: message(e),
+                }))
+            }
+            ValidationErrorsKind::Struct(errors) => collect(errors, &path, result),
+            ValidationErrorsKind::List(items) => {
+                for (index, errors) in items {
+                    collect(errors, &format!("{}[{}]", path, index), result);
+                }
+            }
+        }
+    }
+}
+
+/// The rule's own `message` or a default one for the built in rules.
+fn message(error: &ValidationError) -> String {
+    if let Some(message) = &error.message {
+        return message.to_string();
+    }
+
+    let param = |name: &str| error.params.get(name).filter(|v| !v.is_null());
+    let bounds = || match (param("min"), param("max"), param("equal")) {
+        (_, _, Some(equal)) => format!("must be exactly {}", show(equal)),
+        (Some(min), Some(max), _) => format!("must be between {} and {}", show(min), show(max)),
+        (Some(min), None, _) => format!("must be at least {}", show(min)),
+        (None, Some(max), _) => format!("must be at most {}", show(max)),
+        (None, None, _) => "is out of range".to_string(),
+    };
+
+    match error.code.as_ref() {
+        "email" => "must be a valid email".to_string(),
+        "url" => "must be a valid url".to_string(),
+        "length" => format!("length {}", bounds()),
+        "range" => bounds(),
+        "regex" => "has an invalid format".to_string(),
+        "required" => "is required".to_string(),
+        "must_match" => match param("other") {
+            Some(other) => format!("must match `{}`", show(other)),
+            None => "does not match".to_string(),
+        },
+        code => format!("failed `{}`", code),
+    }
+}
+
+fn show(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        // Range bounds are stored as floats, print `1` rather than `1.0`.
+        Value::Number(n) => match n.as_f64() {
+            Some(f) if f.fract() == 0.0 => format!("{}", f as i64),
+            _ => n.to_string(),
+        },
+        v => v.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server_errors::PROBLEM_JSON;
+    use axum::{body::Body, http::header, http::StatusCode, routing::post, Router};
+    use serde::Deserialize;
+    use tower::ServiceExt;
+
+    #[derive(Deserialize, Validate)]
+    struct Signup {
+        #[validate(email)]
+        email: String,
+        #[validate(length(min = 8))]
+        password: String,
+    }
+
+    async fn signup(ValidatedJson(_): ValidatedJson<Signup>) {}
+
+    #[tokio::test]
+    async fn invalid_bodies_are_rejected_with_every_field() {
+        let request = Request::post("/")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"email": "nope", "password": "short"}"#))
+            .unwrap();
+
+        let response = Router::new()
+            .route("/", post(signup))
+            .oneshot(request)
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], PROBLEM_JSON);
+
+        let mut body = response.into_body();
+        let mut bytes = vec![];
+        while let Some(chunk) = body.data().await {
+            bytes.extend_from_slice(&chunk.unwrap());
+        }
+        let problem: Value = serde_json::from_slice(&bytes).unwrap();
+
+        assert_eq!(problem["code"], "validation_failed");
+        assert_eq!(
+            problem["errors"],
+            serde_json::json!([
+                {"field": "email", "message": "must be a valid email"},
+                {"field": "password", "message": "length must be at least 8"},
+            ])
+        );
+    }
+}
@@ -1,16 +1,14 @@
 use anyhow::{anyhow as error, Result};
-use axum::extract::ws::{Message, WebSocket};
-use futures::{
-    sink::SinkExt,
-    stream::{SplitSink, SplitStream, StreamExt},
-};
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket};
+use futures::{sink::SinkExt, stream::SplitSink};
 use redis::AsyncCommands;
 use serde::{Deserialize, Serialize};
 use std::sync::{Arc, Mutex};
 
-use crate::{
-    app::application_dao::ApplicationDao, application_factory::ApplicationFactory, websocket::room,
-};
+use crate::application_factory::ApplicationFactory;
+
+use crate::metrics::PUBSUB_MESSAGES;
+use crate::telemetry::TraceContext;
 
 use super::{socket::AppSocket, websocket_server::WebsocketServer};
 
@@ -20,6 +18,24 @@ pub struct RoomMessage {
     pub room: String,
 }
 
+/// Payload published to `room::*` channels. Publishers that send a bare string
+/// are still delivered, without trace context.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomEnvelope {
+    pub trace: TraceContext,
+    pub message: String,
+}
+
+impl RoomEnvelope {
+    /// Reads a pubsub payload, falling back to a bare message.
+    pub fn parse(data: &str) -> (Option<TraceContext>, String) {
+        match serde_json::from_str::<RoomEnvelope>(data) {
+            Ok(envelope) => (Some(envelope.trace), envelope.message),
+            Err(_) => (None, data.to_string()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, strum::Display)]
 pub enum Command {
     JOIN(String),
@@ -30,6 +46,7 @@ pub enum Command {
 #[derive(strum::Display)]
 pub enum SocketMessages {
     SocketClose,
+    ServerShutdown,
 }
 
 #[derive(strum::Display, Clone, Deserialize, Serialize, Debug)]
@@ -80,15 +97,24 @@ fn get_appsocket(client_id: &str, state: Arc<Mutex<WebsocketServer>>) -> Result<
     Ok(current_socket)
 }
 
+/// Runs a client command. `request_id` identifies this command in logs and
+/// travels with published room messages.
 pub async fn parse_text_messages(
     msg: String,
     client_id: &str,
+    request_id: &str,
     state: Arc<Mutex<WebsocketServer>>,
     app_fac: Arc<Mutex<ApplicationFactory>>,
 ) -> Result<()> {
-    log::info!("Got message: {}: {}", client_id, msg);
-
     let command: Command = serde_json::from_str(&msg)?;
+    tracing::Span::current().record("command", command.to_string());
+
+    log::info!(
+        "Got {} command from {} ({} bytes)",
+        command,
+        client_id,
+        msg.len()
+    );
 
     let current_appsocket = get_appsocket(client_id, state.clone())?;
 
@@ -114,8 +140,17 @@ pub async fn parse_text_messages(
             };
 
             let channel_name = format!("room::{}", v.room);
+            let envelope = RoomEnvelope {
+                trace: TraceContext {
+                    request_id: request_id.to_string(),
+                    client_id: Some(client_id.to_string()),
+                },
+                message: v.message,
+            };
 
-            conn.publish(channel_name, v.message).await?;
+            conn.publish::<_, _, ()>(channel_name, serde_json::to_string(&envelope)?)
+                .await?;
+            PUBSUB_MESSAGES.with_label_values(&["published"]).inc();
 
             log::info!("Sending message through message parse");
         }
@@ -128,7 +163,15 @@ pub async fn parse_close_messages(
     client_id: &str,
     state: Arc<Mutex<WebsocketServer>>,
 ) -> Result<()> {
-    let current_appsocket = get_appsocket(client_id, state.clone())?;
+    let current_appsocket = match state.lock() {
+        Ok(v) => v.get_client(client_id),
+        Err(e) => return Err(error!(format!("Unable to lock mutex: {}", e.to_string()))),
+    };
+
+    // Already removed when the server closed the socket first
+    let Some(current_appsocket) = current_appsocket else {
+        return Ok(());
+    };
 
     //current_appsocket.socket.send(msg.to_string()).await?;
 
@@ -144,6 +187,30 @@ pub async fn parse_close_messages(
     Ok(())
 }
 
+/// Asks every connected socket to send a going away close frame and leave the
+/// server. Sockets with a full queue are skipped.
+pub fn send_shutdown_messages(state: Arc<Mutex<WebsocketServer>>) -> Result<()> {
+    let clients = match state.lock() {
+        Ok(v) => v.get_clients(),
+        Err(e) => return Err(error!(format!("Unable to lock mutex: {}", e.to_string()))),
+    };
+
+    for client in clients {
+        let response = SocketResponse {
+            message: SocketMessages::ServerShutdown.to_string(),
+            data: None,
+            method_name: String::from("server_shutdown"),
+            response_type: SocketResponseType::Ok,
+        };
+
+        if let Err(e) = client.socket.try_send(response) {
+            log::error!("Unable to close client {}: {}", client.id, e.to_string());
+        }
+    }
+
+    Ok(())
+}
+
 pub async fn parse_text_response(
     msg: &SocketResponse,
     sender: &mut SplitSink<WebSocket, Message>,
@@ -172,6 +239,21 @@ pub async fn parse_sender_message(
         return Ok(());
     }
 
+    if msg.message == SocketMessages::ServerShutdown.to_string() {
+        let frame = CloseFrame {
+            code: close_code::AWAY,
+            reason: "Server shutting down".into(),
+        };
+        if let Err(e) = sender.send(Message::Close(Some(frame))).await {
+            log::error!("Sender socket close error: {}", e.to_string());
+        };
+
+        if let Ok(mut state) = state.lock() {
+            state.remove_client_server(client_id)?;
+        }
+        return Ok(());
+    }
+
     parse_text_response(msg, sender).await?;
 
     Ok(())
@@ -6,6 +6,9 @@ use anyhow::{anyhow as error, Result};
 
 use tokio::sync::oneshot;
 
+/// How often a blocked pubsub read wakes up to check for a stop request.
+const STOP_POLL: std::time::Duration = std::time::Duration::from_millis(500);
+
 #[derive(Debug, Clone)]
 pub struct PubsubPayload {
     pub channel: String,
@@ -36,6 +39,7 @@ impl RedisPubsubAdapter {
     ) -> Result<()> {
         let mut pubsub = con.as_pubsub();
         pubsub.psubscribe(subscribe)?;
+        pubsub.set_read_timeout(Some(STOP_POLL))?;
 
         loop {
             if let Ok(v) = keep_running_resv.try_recv() {
@@ -44,7 +48,11 @@ impl RedisPubsubAdapter {
                 }
             }
 
-            let msg = pubsub.get_message()?;
+            let msg = match pubsub.get_message() {
+                Ok(msg) => msg,
+                Err(e) if e.is_timeout() => continue,
+                Err(e) => return Err(e.into()),
+            };
 
             let channel_name = msg.get_channel_name().to_string();
 
@@ -60,7 +68,7 @@ impl RedisPubsubAdapter {
                     log::error!("Pubsub sender error: {}", e.to_string());
                 };
 
-                log::info!("Got  payload: {channel_name}: {payload}");
+                log::debug!("Got payload on {channel_name}");
             } else if let Err(e) = payload {
                 log::error!("Got pubsub read error: {}", e.to_string());
             }
@@ -110,8 +118,12 @@ impl RedisPubsubAdapter {
         }
     }
 
+    /// True before `run`, after `stop` and once the pubsub loop has exited.
     pub fn is_stopped(&self) -> bool {
-        self.keep_running_sender.is_none()
+        match &self.keep_running_sender {
+            Some(tx) => tx.is_closed(),
+            None => true,
+        }
     }
 }
 
@@ -27,9 +27,11 @@ use futures::{
     stream::{SplitSink, SplitStream, StreamExt},
 };
 use tokio::sync::mpsc;
+use tracing::Instrument;
 
 use crate::websocket::websocket_server::WebsocketServer;
 
+use crate::telemetry;
 use crate::websocket::messages;
 use crate::websocket::room;
 
@@ -49,11 +51,14 @@ pub struct AppSocketResv {
     pub socket: mpsc::Receiver<messages::SocketResponse>,
 }
 
+/// Serves an upgraded socket. `request_id` is the id of the upgrade request,
+/// logged with everything the socket does.
 pub async fn handle_socket(
     socket: WebSocket,
     state: Arc<Mutex<WebsocketServer>>,
     user: Option<DTO<User>>,
     app_fac: Arc<Mutex<ApplicationFactory>>,
+    request_id: String,
 ) {
     log::info!("Socket connected!!");
 
@@ -77,8 +82,12 @@ pub async fn handle_socket(
         state.lock().unwrap().add_client(app_socket.clone());
     }
 
-    tokio::spawn(read(resv, id.to_string(), state.clone(), app_fac.clone()));
-    tokio::spawn(write(sender, app_socket_resc, id, state.clone()));
+    let span = tracing::info_span!("websocket", client_id = %id, request_id = %request_id);
+
+    tokio::spawn(
+        read(resv, id.to_string(), state.clone(), app_fac.clone()).instrument(span.clone()),
+    );
+    tokio::spawn(write(sender, app_socket_resc, id, state.clone()).instrument(span));
 }
 
 async fn send_error_socket(
@@ -107,24 +116,40 @@ pub async fn read(
 ) -> Result<()> {
     while let Some(Ok(msg)) = receiver.next().await {
         if let Message::Text(msg) = msg {
-            if let Err(e) =
-                messages::parse_text_messages(msg, &client_id, state.clone(), app_fac.clone()).await
-            {
-                send_error_socket(
-                    e.to_string().as_str(),
-                    "parse_text_message",
+            let request_id = telemetry::new_request_id();
+            let span = tracing::info_span!(
+                "ws_command",
+                request_id = %request_id,
+                command = tracing::field::Empty,
+            );
+
+            async {
+                if let Err(e) = messages::parse_text_messages(
+                    msg,
                     &client_id,
+                    &request_id,
                     state.clone(),
+                    app_fac.clone(),
                 )
-                .await;
-
-                log::error!("Parse message error: {}", e.to_string());
-            };
+                .await
+                {
+                    send_error_socket(
+                        e.to_string().as_str(),
+                        "parse_text_message",
+                        &client_id,
+                        state.clone(),
+                    )
+                    .await;
+
+                    log::error!("Parse message error: {}", e.to_string());
+                };
+            }
+            .instrument(span)
+            .await;
         } else if let Message::Close(c) = msg {
-            let mut msg = String::from("Unknown reason for closing");
-
-            if let Some(f) = c {
-                msg = format!("Closing... code: {},  reason: {}", f.code, f.reason);
+            match c {
+                Some(f) => log::debug!("Closing... code: {},  reason: {}", f.code, f.reason),
+                None => log::debug!("Unknown reason for closing"),
             }
 
             if let Err(e) = messages::parse_close_messages(&client_id, state.clone()).await {
@@ -160,7 +185,7 @@ pub async fn write(
     //
 
     while let Some(msg) = app_socket_resv.socket.recv().await {
-        log::info!("Resvc message to send: {}", msg.message);
+        log::debug!("Sending {} response to {}", msg.method_name, client_id);
 
         if let Err(e) =
             messages::parse_sender_message(&msg, &mut sender, &client_id, state.clone()).await
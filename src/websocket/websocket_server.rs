@@ -1,34 +1,22 @@
-use std::sync::{Arc, Mutex};
-
 use crate::websocket::socket;
 
 use anyhow::{anyhow as error, Result};
 
-use crate::application_factory::ApplicationFactory;
 use crate::websocket::room;
-use serde::{Deserialize, Serialize};
-
-use super::{redis_pubsub::RedisPubsubAdapter, socket::AppSocket};
 
-#[derive(Debug, Serialize, Clone)]
-struct ServerResponse<T> {
-    message: String,
-    result: T,
-    code: u32,
-}
+use super::socket::AppSocket;
 
+#[derive(Default)]
 pub struct WebsocketServer {
     sockets: Vec<socket::AppSocket>,
     rooms: Vec<room::Room>,
-    factory: Arc<Mutex<ApplicationFactory>>,
 }
 
 impl WebsocketServer {
-    pub fn new(fac: Arc<Mutex<ApplicationFactory>>) -> Self {
+    pub fn new() -> Self {
         Self {
             sockets: vec![],
             rooms: vec![],
-            factory: fac,
         }
     }
 
@@ -104,6 +92,18 @@ impl WebsocketServer {
         }
     }
 
+    pub fn get_clients(&self) -> Vec<socket::AppSocket> {
+        self.sockets.clone()
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.sockets.len()
+    }
+
+    pub fn room_count(&self) -> usize {
+        self.rooms.len()
+    }
+
     pub async fn send_to_room(&mut self, room_id: &str, message: &str) -> Result<()> {
         let room = self.get_room(room_id);
         if let Some(room) = room {
@@ -126,7 +126,3 @@ impl WebsocketServer {
         Ok(())
     }
 }
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct HelloResponse {
-    result: String,
-}